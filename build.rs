@@ -0,0 +1,4 @@
+// the migrations are embedded by 'sqlx::migrate!', a new migration file has to trigger a rebuild
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
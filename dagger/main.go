@@ -54,7 +54,7 @@ func (m *Dagger) Lint(ctx context.Context) (string, error) {
 // Return the result of running the tests
 func (m *Dagger) Test(ctx context.Context) (string, error) {
 	return m.BuildEnv().
-		WithExec([]string{"cargo", "sqlx", "prepare", "--workspace", "--check"}).
+		WithExec([]string{"cargo", "sqlx", "prepare", "--workspace", "--check", "--", "--all-targets"}).
 		WithExec([]string{"cargo", "test"}).
 		Stdout(ctx)
 }
@@ -67,7 +67,14 @@ func (m *Dagger) Coverage(ctx context.Context) (string, error) {
 		Stdout(ctx)
 }
 
-// Run formatter, linter, tests and coverage concurrently
+// Return the content of the release image, built from the Dockerfile
+func (m *Dagger) Image(ctx context.Context) (string, error) {
+	return m.Source.DockerBuild().
+		WithExec([]string{"ls", "-l", "/app"}).
+		Stdout(ctx)
+}
+
+// Run formatter, linter, tests, coverage and the image build concurrently
 func (m *Dagger) RunAllTests(ctx context.Context) error {
 	// Create error group
 	eg, gctx := errgroup.WithContext(ctx)
@@ -95,6 +102,12 @@ func (m *Dagger) RunAllTests(ctx context.Context) error {
 		_, err := m.Coverage(gctx)
 		return err
 	})
+
+	// Build the release image
+	eg.Go(func() error {
+		_, err := m.Image(gctx)
+		return err
+	})
 	// Wait for all tests to complete
 	// If any test fails, the error will be returned
 	return eg.Wait()
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues(
+    newsletter_issue_id uuid NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    -- an issue is either published right away or waits for its scheduled time
+    scheduled_at timestamptz NULL,
+    published_at timestamptz NULL,
+    PRIMARY KEY(newsletter_issue_id)
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE users(
+    user_id uuid PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    -- where test sends of newsletter issues are delivered
+    email TEXT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL;
+    ALTER TABLE newsletter_issues ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT false;
+    -- existing issues are addressed by their id
+    UPDATE newsletter_issues
+        SET slug = newsletter_issue_id::text
+        WHERE slug IS NULL;
+    ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+    ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
+COMMIT;
@@ -0,0 +1,11 @@
+-- Add migration script here
+BEGIN;
+    -- every subscriber gets a token for the unsubscribe link of the issues they receive
+    ALTER TABLE subscriptions ADD COLUMN unsubscribe_token TEXT NULL;
+    ALTER TABLE subscriptions ADD COLUMN unsubscribed_at timestamptz NULL;
+    UPDATE subscriptions
+        SET unsubscribe_token = replace(gen_random_uuid()::text, '-', '')
+        WHERE unsubscribe_token IS NULL;
+    ALTER TABLE subscriptions ALTER COLUMN unsubscribe_token SET NOT NULL;
+    ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_unsubscribe_token_key UNIQUE (unsubscribe_token);
+COMMIT;
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE email_events(
+    event_id uuid NOT NULL,
+    -- Postmark retries deliveries, its own id makes the ingestion idempotent
+    postmark_id BIGINT NOT NULL,
+    record_type TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    email TEXT NOT NULL,
+    message_id TEXT NULL,
+    description TEXT NULL,
+    details TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL,
+    PRIMARY KEY (event_id),
+    UNIQUE (record_type, postmark_id)
+);
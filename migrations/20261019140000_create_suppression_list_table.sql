@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE suppression_list(
+    entry_id uuid NOT NULL,
+    -- either a full address or a whole domain, stored lowercase
+    kind TEXT NOT NULL CHECK (kind IN ('address', 'domain')),
+    value TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    source TEXT NOT NULL CHECK (source IN ('manual', 'bounce', 'complaint', 'legal_request')),
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (entry_id),
+    UNIQUE (kind, value)
+);
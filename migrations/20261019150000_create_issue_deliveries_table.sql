@@ -0,0 +1,12 @@
+-- Add migration script here
+-- the queue forgets a task once it is executed, deliveries keep track of every attempt
+CREATE TABLE issue_deliveries(
+    delivery_id uuid NOT NULL,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('sent', 'failed')),
+    attempted_at timestamptz NOT NULL,
+    PRIMARY KEY (delivery_id)
+);
+CREATE INDEX issue_deliveries_newsletter_issue_id_idx ON issue_deliveries (newsletter_issue_id);
@@ -0,0 +1,16 @@
+-- Add migration script here
+BEGIN;
+    ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT false;
+    ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT false;
+    CREATE TABLE tracking_events(
+        event_id uuid NOT NULL,
+        delivery_id uuid NOT NULL
+            REFERENCES issue_deliveries (delivery_id),
+        kind TEXT NOT NULL CHECK (kind IN ('open', 'click')),
+        -- the destination of click events
+        url TEXT NULL,
+        occurred_at timestamptz NOT NULL,
+        PRIMARY KEY (event_id)
+    );
+    CREATE INDEX tracking_events_delivery_id_idx ON tracking_events (delivery_id);
+COMMIT;
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- emails are written here in the transaction that requires them, then sent by the dispatcher
+CREATE TABLE email_outbox(
+    message_id uuid NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'sent', 'skipped', 'failed')),
+    n_attempts SMALLINT NOT NULL DEFAULT 0,
+    next_attempt_at timestamptz NOT NULL,
+    created_at timestamptz NOT NULL,
+    processed_at timestamptz NULL,
+    PRIMARY KEY (message_id)
+);
+CREATE INDEX email_outbox_pending_idx ON email_outbox (next_attempt_at)
+    WHERE status = 'pending';
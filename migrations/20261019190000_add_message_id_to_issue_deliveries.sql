@@ -0,0 +1,6 @@
+-- the id Postmark gives to an accepted email, its bounces are reported with it
+BEGIN;
+    ALTER TABLE issue_deliveries ADD COLUMN message_id TEXT NULL;
+    CREATE INDEX issue_deliveries_message_id_idx ON issue_deliveries (message_id);
+    CREATE INDEX email_events_message_id_idx ON email_events (message_id);
+COMMIT;
@@ -0,0 +1,6 @@
+-- a bucket is as good as a new one once it is full again, the row can then be deleted.
+-- null for buckets that never refill
+BEGIN;
+    ALTER TABLE rate_limit_buckets ADD COLUMN full_at timestamptz NULL;
+    CREATE INDEX rate_limit_buckets_full_at_idx ON rate_limit_buckets (full_at);
+COMMIT;
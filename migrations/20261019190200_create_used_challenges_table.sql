@@ -0,0 +1,7 @@
+-- the nonces of the challenges already used by a signup, kept until the challenges expire
+CREATE TABLE used_challenges(
+    nonce TEXT NOT NULL,
+    expires_at timestamptz NOT NULL,
+    PRIMARY KEY (nonce)
+);
+CREATE INDEX used_challenges_expires_at_idx ON used_challenges (expires_at);
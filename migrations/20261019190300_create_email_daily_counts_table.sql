@@ -0,0 +1,6 @@
+-- the emails handed to the provider each day (UTC), shared by every instance for the daily cap
+CREATE TABLE email_daily_counts(
+    day DATE NOT NULL,
+    sent BIGINT NOT NULL,
+    PRIMARY KEY (day)
+);
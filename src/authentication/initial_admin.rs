@@ -0,0 +1,45 @@
+use crate::configuration::InitialAdminSettings;
+use anyhow::Context;
+use argon2::PasswordHash;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// the first admin comes from the configuration rather than from a migration, so no deployment
+// starts with a known password. nothing happens once the database has a user
+#[tracing::instrument(name = "Create the initial admin", skip_all)]
+pub async fn create_initial_admin(
+    pool: &PgPool,
+    settings: &InitialAdminSettings,
+) -> Result<(), anyhow::Error> {
+    let Some(password_hash) = &settings.password_hash else {
+        return Ok(());
+    };
+    PasswordHash::new(password_hash.expose_secret()).map_err(|e| {
+        anyhow::anyhow!("The initial admin password hash is not a valid PHC string: {e}")
+    })?;
+
+    // concurrent instances may both find the table empty, the username is unique
+    let created = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, password_hash, email)
+        SELECT $1, $2, $3, $4
+        WHERE NOT EXISTS (SELECT 1 FROM users)
+        ON CONFLICT (username) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        settings.username,
+        password_hash.expose_secret(),
+        settings.email,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create the initial admin.")?
+    .rows_affected()
+        == 1;
+    if created {
+        tracing::info!(username = %settings.username, "Created the initial admin.");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::configuration::{MetricsSettings, WebhookSettings};
+use crate::routes::unexpected_error_status;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use anyhow::Context;
+use base64::Engine;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::ops::Deref;
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+#[derive(Copy, Clone, Debug)]
+pub struct UserId(Uuid);
+
+impl std::fmt::Display for UserId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Deref for UserId {
+    type Target = Uuid;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// only requests carrying valid credentials reach the wrapped handlers,
+// which can then retrieve the authenticated user via 'web::ReqData<UserId>'
+pub async fn reject_anonymous_users(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let credentials = basic_authentication(req.headers()).map_err(|e| unauthorized("admin", e))?;
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .expect("The connection pool is not registered as application data.")
+        .clone();
+
+    match validate_credentials(credentials, &pool).await {
+        Ok(user_id) => {
+            req.extensions_mut().insert(UserId(user_id));
+            next.call(req).await
+        }
+        Err(AuthError::InvalidCredentials(e)) => Err(unauthorized("admin", e)),
+        Err(AuthError::UnexpectedError(e)) => {
+            let status = unexpected_error_status(e.as_ref());
+            Err(InternalError::new(e, status).into())
+        }
+    }
+}
+
+// the webhooks authenticate with credentials of their own, so the webhook configuration
+// of the email provider does not hold the credentials of an admin
+pub async fn reject_unknown_webhook_senders(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let credentials =
+        basic_authentication(req.headers()).map_err(|e| unauthorized("webhooks", e))?;
+    let settings = req
+        .app_data::<web::Data<WebhookSettings>>()
+        .expect("The webhook settings are not registered as application data.");
+
+    if !credentials.match_exactly(&settings.username, &settings.password) {
+        return Err(unauthorized(
+            "webhooks",
+            anyhow::anyhow!("Invalid webhook credentials."),
+        ));
+    }
+
+    next.call(req).await
+}
+
+// the metrics tell how many people sign up and how much is queued, they are kept from the
+// public with credentials only the scraper holds
+pub async fn reject_unknown_metrics_scrapers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let credentials =
+        basic_authentication(req.headers()).map_err(|e| unauthorized("metrics", e))?;
+    let settings = req
+        .app_data::<web::Data<MetricsSettings>>()
+        .expect("The metrics settings are not registered as application data.");
+
+    if !credentials.match_exactly(&settings.username, &settings.password) {
+        return Err(unauthorized(
+            "metrics",
+            anyhow::anyhow!("Invalid metrics credentials."),
+        ));
+    }
+
+    next.call(req).await
+}
+
+impl Credentials {
+    // compared in constant time, so response times do not reveal how much of them matched
+    fn match_exactly(&self, username: &str, password: &Secret<String>) -> bool {
+        let username_matches = self.username.as_bytes().ct_eq(username.as_bytes());
+        let password_matches = self
+            .password
+            .expose_secret()
+            .as_bytes()
+            .ct_eq(password.expose_secret().as_bytes());
+
+        bool::from(username_matches & password_matches)
+    }
+}
+
+fn unauthorized(realm: &str, e: anyhow::Error) -> actix_web::Error {
+    let mut response = HttpResponse::Unauthorized().finish();
+    let header_value = HeaderValue::from_str(&format!(r#"Basic realm="{realm}""#)).unwrap();
+    response
+        .headers_mut()
+        .insert(header::WWW_AUTHENTICATE, header_value);
+
+    InternalError::from_response(e, response).into()
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    // the header value, if present, must be a valid UTF8 string
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // split into two segments, using ':' as delimiter
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: Secret::new(password),
+    })
+}
@@ -0,0 +1,9 @@
+mod initial_admin;
+mod middleware;
+mod password;
+
+pub use initial_admin::create_initial_admin;
+pub use middleware::{
+    reject_anonymous_users, reject_unknown_metrics_scrapers, reject_unknown_webhook_senders, UserId,
+};
+pub use password::{compute_password_hash, validate_credentials, AuthError, Credentials};
@@ -0,0 +1,213 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+
+use crate::configuration::BotProtectionSettings;
+
+// a challenge is a signed timestamp with some randomness, so that proofs of work cannot be
+// computed once and reused. the client solves it by finding a 'solution' such that
+// sha256("{challenge}:{solution}") starts with the configured number of zero bits.
+// the nonce of a verified challenge is recorded until it expires, a challenge is only
+// accepted if its nonce was not recorded before
+
+#[derive(Debug, PartialEq)]
+pub enum ChallengeError {
+    Missing,
+    Forged,
+    TooFast,
+    Expired,
+    Unsolved,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VerifiedChallenge {
+    pub nonce: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub fn issue_challenge(settings: &BotProtectionSettings, now: DateTime<Utc>) -> String {
+    let mut payload = now.timestamp().to_be_bytes().to_vec();
+    payload.extend_from_slice(&thread_rng().gen::<[u8; 16]>());
+    let tag = mac(settings, &payload).finalize().into_bytes();
+
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(tag)
+    )
+}
+
+pub fn verify_challenge(
+    settings: &BotProtectionSettings,
+    challenge: Option<&str>,
+    solution: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<VerifiedChallenge, ChallengeError> {
+    let challenge = challenge.ok_or(ChallengeError::Missing)?;
+    let issued_at = issued_at(settings, challenge).ok_or(ChallengeError::Forged)?;
+
+    let age = now.timestamp() - issued_at;
+    if age < settings.min_solve_seconds as i64 {
+        return Err(ChallengeError::TooFast);
+    }
+    if age > settings.max_challenge_age_seconds as i64 {
+        return Err(ChallengeError::Expired);
+    }
+
+    let solution = solution.unwrap_or_default();
+    if !is_solved(challenge, solution, settings.proof_of_work_difficulty) {
+        return Err(ChallengeError::Unsolved);
+    }
+
+    // the signed payload holds the randomness, it identifies the challenge
+    let (nonce, _) = challenge.split_once('.').ok_or(ChallengeError::Forged)?;
+    let expires_at = DateTime::from_timestamp(issued_at, 0)
+        .and_then(|issued_at| {
+            issued_at.checked_add_signed(chrono::Duration::seconds(
+                settings.max_challenge_age_seconds as i64,
+            ))
+        })
+        .ok_or(ChallengeError::Forged)?;
+
+    Ok(VerifiedChallenge {
+        nonce: nonce.to_owned(),
+        expires_at,
+    })
+}
+
+pub fn is_solved(challenge: &str, solution: &str, difficulty: u32) -> bool {
+    let hash = Sha256::digest(format!("{challenge}:{solution}"));
+
+    leading_zero_bits(&hash) >= difficulty
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn issued_at(settings: &BotProtectionSettings, challenge: &str) -> Option<i64> {
+    let (payload, tag) = challenge.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+    mac(settings, &payload).verify_slice(&tag).ok()?;
+
+    Some(i64::from_be_bytes(payload.get(..8)?.try_into().ok()?))
+}
+
+fn mac(settings: &BotProtectionSettings, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(settings.hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size.");
+    mac.update(payload);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_solved, issue_challenge, verify_challenge, ChallengeError};
+    use crate::configuration::BotProtectionSettings;
+    use chrono::{Duration, Utc};
+    use claims::{assert_err_eq, assert_ok};
+    use secrecy::Secret;
+
+    fn settings(proof_of_work_difficulty: u32) -> BotProtectionSettings {
+        BotProtectionSettings {
+            require_challenge: true,
+            hmac_secret: Secret::new("secret".into()),
+            min_solve_seconds: 3,
+            max_challenge_age_seconds: 3600,
+            proof_of_work_difficulty,
+        }
+    }
+
+    fn solve(challenge: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|n| n.to_string())
+            .find(|solution| is_solved(challenge, solution, difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_solved_challenge_submitted_in_time_is_accepted() {
+        let settings = settings(8);
+        let now = Utc::now();
+        let challenge = issue_challenge(&settings, now);
+        let solution = solve(&challenge, 8);
+
+        assert_ok!(verify_challenge(
+            &settings,
+            Some(&challenge),
+            Some(&solution),
+            now + Duration::seconds(10)
+        ));
+    }
+
+    #[test]
+    fn submissions_faster_than_a_human_are_rejected() {
+        let settings = settings(0);
+        let now = Utc::now();
+        let challenge = issue_challenge(&settings, now);
+
+        assert_err_eq!(
+            verify_challenge(&settings, Some(&challenge), None, now),
+            ChallengeError::TooFast
+        );
+    }
+
+    #[test]
+    fn old_challenges_are_rejected() {
+        let settings = settings(0);
+        let now = Utc::now();
+        let challenge = issue_challenge(&settings, now);
+
+        assert_err_eq!(
+            verify_challenge(&settings, Some(&challenge), None, now + Duration::hours(2)),
+            ChallengeError::Expired
+        );
+    }
+
+    #[test]
+    fn challenges_signed_with_another_secret_are_rejected() {
+        let now = Utc::now();
+        let mut other = settings(0);
+        other.hmac_secret = Secret::new("another secret".into());
+        let challenge = issue_challenge(&other, now);
+
+        assert_err_eq!(
+            verify_challenge(
+                &settings(0),
+                Some(&challenge),
+                None,
+                now + Duration::seconds(10)
+            ),
+            ChallengeError::Forged
+        );
+    }
+
+    #[test]
+    fn unsolved_challenges_are_rejected() {
+        let settings = settings(32);
+        let now = Utc::now();
+        let challenge = issue_challenge(&settings, now);
+
+        assert_err_eq!(
+            verify_challenge(
+                &settings,
+                Some(&challenge),
+                Some("not a solution"),
+                now + Duration::seconds(10)
+            ),
+            ChallengeError::Unsolved
+        );
+    }
+}
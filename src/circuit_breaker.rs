@@ -0,0 +1,173 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// stops calling a provider that keeps failing, so callers fail fast instead of waiting
+// out the request timeout. after a while, a single probe is let through to test the waters
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Closed { consecutive_failures: u32 },
+    Open { until: Instant },
+    // a probe that never reports back, e.g. because its request was cancelled,
+    // is given up on after 'open_duration' and another probe goes out
+    HalfOpen { probe_expires_at: Instant },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The email provider is unavailable, the circuit breaker is open.")]
+pub struct CircuitOpen;
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    // to be called before each request, which must then be reported as a success or a failure
+    pub fn try_acquire(&self) -> Result<(), CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until }
+            | State::HalfOpen {
+                probe_expires_at: until,
+            } if now < until => Err(CircuitOpen),
+            State::Open { .. } | State::HalfOpen { .. } => {
+                tracing::info!("Probing the email provider.");
+                *state = State::HalfOpen {
+                    probe_expires_at: now + self.open_duration,
+                };
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::info!("The email provider recovered, closing the circuit breaker.");
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let consecutive_failures = match *state {
+            State::Closed {
+                consecutive_failures,
+            } => consecutive_failures + 1,
+            // a failed probe opens the circuit again right away
+            State::Open { .. } | State::HalfOpen { .. } => self.failure_threshold,
+        };
+
+        *state = if consecutive_failures >= self.failure_threshold {
+            tracing::warn!("The email provider keeps failing, opening the circuit breaker.");
+            State::Open {
+                until: Instant::now() + self.open_duration,
+            }
+        } else {
+            State::Closed {
+                consecutive_failures,
+            }
+        };
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() < until => CircuitState::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitState};
+    use claims::{assert_err, assert_ok};
+    use std::time::Duration;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(3, Duration::from_secs(30))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_circuit_opens_after_consecutive_failures() {
+        let breaker = breaker();
+
+        for _ in 0..3 {
+            assert_ok!(breaker.try_acquire());
+            breaker.record_failure();
+        }
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_err!(breaker.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_success_resets_the_failure_count() {
+        let breaker = breaker();
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_single_probe_is_let_through_once_the_circuit_has_been_open_long_enough() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_ok!(breaker.try_acquire());
+        assert_err!(breaker.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_successful_probe_closes_the_circuit_and_a_failed_one_opens_it_again() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        tokio::time::advance(Duration::from_secs(31)).await;
+
+        assert_ok!(breaker.try_acquire());
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::Open);
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert_ok!(breaker.try_acquire());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,69 @@
+const USAGE: &str = "Usage:
+    zero2prod                   serves the application
+    zero2prod migrate up        applies the pending migrations
+    zero2prod migrate status    lists the migrations and whether they are applied";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    Migrate(MigrateCommand),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrateCommand {
+    Up,
+    Status,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Unknown command '{0}'.\n\n{USAGE}")]
+pub struct UnknownCommand(String);
+
+impl Command {
+    // 'args' excludes the name of the binary
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, UnknownCommand> {
+        let args: Vec<String> = args.into_iter().collect();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+        match args.as_slice() {
+            [] | ["serve"] => Ok(Self::Serve),
+            ["migrate", "up"] => Ok(Self::Migrate(MigrateCommand::Up)),
+            ["migrate", "status"] => Ok(Self::Migrate(MigrateCommand::Status)),
+            _ => Err(UnknownCommand(args.join(" "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, MigrateCommand};
+
+    fn parse(args: &[&str]) -> Option<Command> {
+        Command::parse(args.iter().map(|arg| arg.to_string())).ok()
+    }
+
+    #[test]
+    fn no_arguments_serve_the_application() {
+        assert_eq!(parse(&[]), Some(Command::Serve));
+        assert_eq!(parse(&["serve"]), Some(Command::Serve));
+    }
+
+    #[test]
+    fn migrate_subcommands_are_parsed() {
+        assert_eq!(
+            parse(&["migrate", "up"]),
+            Some(Command::Migrate(MigrateCommand::Up))
+        );
+        assert_eq!(
+            parse(&["migrate", "status"]),
+            Some(Command::Migrate(MigrateCommand::Status))
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        assert_eq!(parse(&["migrate"]), None);
+        assert_eq!(parse(&["migrate", "down"]), None);
+        assert_eq!(parse(&["serve", "now"]), None);
+    }
+}
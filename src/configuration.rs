@@ -1,13 +1,55 @@
-use crate::domain::SubscriberEmail;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::domain::{EmailPolicy, SubscriberEmail};
+use crate::email_client::EmailClient;
+use crate::email_domain_check::EmailDomainChecker;
+use crate::outbound_rate_limit::{DailyCap, OutboundRateLimiter};
+use crate::telemetry::RedactionPolicy;
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sqlx::PgPool;
+use std::net::SocketAddr;
 
 #[derive(Clone, serde::Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub tracking: TrackingSettings,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_policy: EmailPolicySettings,
+    pub email_domain_check: EmailDomainCheckSettings,
+    pub telemetry: TelemetrySettings,
+    pub redaction: RedactionSettings,
+    pub initial_admin: InitialAdminSettings,
+    pub webhooks: WebhookSettings,
+    pub metrics: MetricsSettings,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct WebhookSettings {
+    // sent by Postmark as 'Basic' credentials, they only grant access to the webhooks
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct MetricsSettings {
+    // sent by the Prometheus scraper as 'Basic' credentials, they only grant access to '/metrics'
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct InitialAdminSettings {
+    pub username: String,
+    pub email: String,
+    // an argon2 PHC string. without one, no admin is created
+    #[serde(default)]
+    pub password_hash: Option<Secret<String>>,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -17,6 +59,13 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    // once a shutdown starts, the readiness check fails for this long before the server stops
+    // accepting connections, so load balancers have time to take the instance out of rotation
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_drain_seconds: u64,
+    // requests still in flight after this long are dropped
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub shutdown_grace_period_seconds: u64,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -29,6 +78,34 @@ pub struct DatabaseSettings {
     pub database_name: String,
     // determine if we demand the connection to be encrypted or not
     pub require_ssl: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    // a request waiting longer than this for a connection fails with a 503, rather than hanging
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub idle_timeout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_lifetime_seconds: u64,
+    // postgres cancels the statements running longer than this
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub statement_timeout_milliseconds: u64,
+    // identifies the connections of the application in 'pg_stat_activity'
+    pub application_name: String,
+    // applies the pending migrations before the application starts serving
+    pub run_migrations_on_startup: bool,
+    // listings, reports and the archive read from the replica when there is one
+    #[serde(default)]
+    pub read_replica: Option<ReadReplicaSettings>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct ReadReplicaSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
 }
 
 impl DatabaseSettings {
@@ -48,7 +125,35 @@ impl DatabaseSettings {
     }
 
     pub fn with_db(&self) -> PgConnectOptions {
-        self.without_db().database(&self.database_name)
+        let statement_timeout = format!("{}ms", self.statement_timeout_milliseconds);
+        self.without_db()
+            .database(&self.database_name)
+            .application_name(&self.application_name)
+            .options([("statement_timeout", statement_timeout.as_str())])
+    }
+
+    // the replica shares the credentials, database and pool settings of the primary.
+    // its connections are named apart, so they can be told from the primary ones
+    pub fn read_replica(&self) -> Option<DatabaseSettings> {
+        self.read_replica.as_ref().map(|replica| DatabaseSettings {
+            host: replica.host.clone(),
+            port: replica.port,
+            application_name: format!("{}-replica", self.application_name),
+            read_replica: None,
+            ..self.clone()
+        })
+    }
+
+    pub fn acquire_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.acquire_timeout_milliseconds)
+    }
+
+    pub fn idle_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idle_timeout_seconds)
+    }
+
+    pub fn max_lifetime(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.max_lifetime_seconds)
     }
 }
 
@@ -58,9 +163,49 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    // provider limits, no limit when unset
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub messages_per_second: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub daily_cap: Option<u32>,
+    pub circuit_breaker: CircuitBreakerSettings,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct CircuitBreakerSettings {
+    // consecutive failed requests before the provider is considered down
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    // how long requests fail fast before a probe is sent to the provider
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub open_duration_milliseconds: u64,
+}
+
+impl CircuitBreakerSettings {
+    pub fn breaker(&self) -> CircuitBreaker {
+        CircuitBreaker::new(
+            self.failure_threshold,
+            std::time::Duration::from_millis(self.open_duration_milliseconds),
+        )
+    }
 }
 
 impl EmailClientSettings {
+    // the daily cap is counted in the database behind 'pool'
+    pub fn client(self, pool: PgPool) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        let daily_cap = self.daily_cap.map(|limit| DailyCap { limit, pool });
+        EmailClient::new(
+            self.base_url,
+            sender_email,
+            self.authorization_token,
+            timeout,
+            OutboundRateLimiter::new(self.messages_per_second, daily_cap),
+            self.circuit_breaker.breaker(),
+        )
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -70,6 +215,131 @@ impl EmailClientSettings {
     }
 }
 
+#[derive(Clone, serde::Deserialize)]
+pub struct TrackingSettings {
+    // when disabled, no issue is tracked whatever its own settings, for privacy-sensitive lists
+    pub enabled: bool,
+    // signs the tokens of tracking links, so they cannot be forged
+    pub hmac_secret: Secret<String>,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    // the client ip is the last entry of the 'X-Forwarded-For' header,
+    // only safe when every request goes through a single reverse proxy appending to it
+    pub trust_proxy_headers: bool,
+    pub per_ip: TokenBucketSettings,
+    pub per_email: TokenBucketSettings,
+}
+
+#[derive(Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    InMemory,
+    // shares the buckets between all the instances of the application
+    Postgres,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+pub struct TokenBucketSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_per_minute: u32,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct BotProtectionSettings {
+    // when disabled, the honeypot field is still checked but challenges are not required
+    pub require_challenge: bool,
+    // signs the challenges issued by '/subscriptions/challenge'
+    pub hmac_secret: Secret<String>,
+    // a form submitted faster than this was not filled by a human
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_solve_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_challenge_age_seconds: u64,
+    // leading zero bits required in the proof-of-work hash, 0 only checks the timestamp
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub proof_of_work_difficulty: u32,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailPolicySettings {
+    // checked against the list bundled with the application
+    pub reject_disposable_domains: bool,
+    pub reject_role_accounts: bool,
+}
+
+impl EmailPolicySettings {
+    pub fn policy(&self) -> EmailPolicy {
+        EmailPolicy::new(self.reject_disposable_domains, self.reject_role_accounts)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct EmailDomainCheckSettings {
+    pub enabled: bool,
+    // the DNS server queried for MX records, e.g. a resolver running next to the application
+    pub resolver_address: SocketAddr,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+impl EmailDomainCheckSettings {
+    pub fn checker(&self) -> EmailDomainChecker {
+        if self.enabled {
+            EmailDomainChecker::new(
+                self.resolver_address,
+                std::time::Duration::from_millis(self.timeout_milliseconds),
+            )
+        } else {
+            EmailDomainChecker::disabled()
+        }
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct TelemetrySettings {
+    // the OTLP/HTTP traces endpoint of the collector, e.g. 'http://localhost:4318/v1/traces'.
+    // spans are only exported when it is set
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub export_timeout_milliseconds: u64,
+}
+
+// names of the log fields to redact, wherever they appear in a record
+#[derive(Clone, serde::Deserialize)]
+pub struct RedactionSettings {
+    // partially masked, e.g. 'u***@gmail.com'
+    pub masked_fields: Vec<String>,
+    // replaced by a hash, so records about the same value can still be correlated
+    pub hashed_fields: Vec<String>,
+    pub removed_fields: Vec<String>,
+    // fields holding a URL, the values of their query parameters are masked
+    pub url_fields: Vec<String>,
+    // route templates with secret segments in braces, e.g. '/t/c/{token}'
+    pub secret_paths: Vec<String>,
+    pub scrub_emails_in_text: bool,
+    // keys the hashes of the 'hashed_fields'
+    pub hmac_secret: Secret<String>,
+}
+
+impl RedactionSettings {
+    pub fn policy(&self) -> RedactionPolicy {
+        RedactionPolicy::new(
+            &self.masked_fields,
+            &self.hashed_fields,
+            &self.removed_fields,
+            self.scrub_emails_in_text,
+        )
+        .with_urls(&self.url_fields, &self.secret_paths)
+        .with_hash_key(self.hmac_secret.clone())
+    }
+}
+
 // possible runtime environment for our application
 pub enum Environment {
     Local,
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+
+use super::SubscriberEmail;
+
+const DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+// shared mailboxes, nobody in particular asked for the newsletter
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "webmaster",
+];
+
+// rules on top of the syntax of an address, applied to new subscribers only
+pub struct EmailPolicy {
+    disposable_domains: HashSet<String>,
+    reject_role_accounts: bool,
+}
+
+impl EmailPolicy {
+    pub fn new(reject_disposable_domains: bool, reject_role_accounts: bool) -> Self {
+        let disposable_domains = if reject_disposable_domains {
+            DISPOSABLE_DOMAINS
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_lowercase)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        Self {
+            disposable_domains,
+            reject_role_accounts,
+        }
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        if self.is_disposable(email.domain()) {
+            return Err(format!(
+                "{} belongs to a disposable email provider.",
+                email.as_ref()
+            ));
+        }
+        if self.reject_role_accounts && is_role_account(email.local_part()) {
+            return Err(format!(
+                "{} is a role account, please subscribe with a personal address.",
+                email.as_ref()
+            ));
+        }
+
+        Ok(())
+    }
+
+    // 'mail.yopmail.com' is as disposable as 'yopmail.com'
+    fn is_disposable(&self, domain: &str) -> bool {
+        std::iter::successors(Some(domain), |domain| {
+            domain.split_once('.').map(|(_, parent)| parent)
+        })
+        .any(|domain| self.disposable_domains.contains(domain))
+    }
+}
+
+// 'admin+newsletter' is still the admin mailbox
+fn is_role_account(local_part: &str) -> bool {
+    let mailbox = local_part.split('+').next().unwrap_or_default();
+
+    ROLE_ACCOUNTS.contains(&mailbox.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailPolicy;
+    use crate::domain::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        let policy = EmailPolicy::new(true, false);
+
+        assert_err!(policy.check(&email("ursula@mailinator.com")));
+        assert_err!(policy.check(&email("ursula@mail.YOPMAIL.com")));
+        assert_ok!(policy.check(&email("ursula@gmail.com")));
+    }
+
+    #[test]
+    fn role_accounts_are_rejected_when_configured() {
+        let policy = EmailPolicy::new(false, true);
+
+        assert_err!(policy.check(&email("NoReply@example.com")));
+        assert_err!(policy.check(&email("admin+news@example.com")));
+        assert_ok!(policy.check(&email("ursula@example.com")));
+        assert_ok!(EmailPolicy::new(false, false).check(&email("admin@example.com")));
+    }
+
+    #[test]
+    fn each_rule_has_its_own_message() {
+        let policy = EmailPolicy::new(true, true);
+
+        let disposable = policy.check(&email("ursula@mailinator.com")).unwrap_err();
+        let role = policy.check(&email("admin@example.com")).unwrap_err();
+
+        assert!(disposable.contains("disposable email provider"));
+        assert!(role.contains("role account"));
+    }
+}
@@ -1,7 +1,9 @@
+mod email_policy;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
 
+pub use email_policy::EmailPolicy;
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -1,6 +1,8 @@
 use validator::validate_email;
 
-#[derive(Debug)]
+use super::EmailPolicy;
+
+#[derive(Clone, Debug)]
 pub struct SubscriberEmail(String);
 
 impl AsRef<str> for SubscriberEmail {
@@ -10,19 +12,46 @@ impl AsRef<str> for SubscriberEmail {
 }
 
 impl SubscriberEmail {
+    // the domain is case-insensitive and stored in its ASCII form, so that the same address
+    // always compares equal. the local part is left as typed, its case may matter
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if validate_email(&s) {
-            Ok(Self(s))
+        let Some((local_part, domain)) = s.rsplit_once('@') else {
+            return Err(format!("{s} is not a valid subscriber email."));
+        };
+        let domain = idna::domain_to_ascii(domain)
+            .map_err(|_| format!("{domain} is not a valid internationalised domain name."))?;
+        let email = format!("{local_part}@{domain}");
+
+        if validate_email(&email) {
+            Ok(Self(email))
         } else {
             Err(format!("{s} is not a valid subscriber email."))
         }
     }
+
+    // parses the address, then applies the rules that go beyond its syntax
+    pub fn parse_with_policy(s: String, policy: &EmailPolicy) -> Result<SubscriberEmail, String> {
+        let email = Self::parse(s)?;
+        policy.check(&email)?;
+
+        Ok(email)
+    }
+
+    pub fn local_part(&self) -> &str {
+        self.0
+            .rsplit_once('@')
+            .map_or("", |(local_part, _)| local_part)
+    }
+
+    pub fn domain(&self) -> &str {
+        self.0.rsplit_once('@').map_or("", |(_, domain)| domain)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     // we are using the 'SafeEmail' faker, and we also need the 'Fake' trait
     // to get access to the '.fake' method on 'SafeEmail'
     use fake::faker::internet::en::SafeEmail;
@@ -60,6 +89,31 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn the_domain_is_lowercased() {
+        let email = SubscriberEmail::parse("Ursula@Example.COM".to_string()).unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_converted_to_punycode() {
+        let email = SubscriberEmail::parse("ursula@bücher.example".to_string()).unwrap();
+        assert_eq!(email.domain(), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn invalid_internationalised_domains_are_rejected() {
+        let email = "ursula@xn--a.example".to_string();
+        let error = SubscriberEmail::parse(email).unwrap_err();
+        assert!(error.contains("internationalised domain"));
+    }
+
+    #[test]
+    fn the_local_part_is_left_as_typed() {
+        let email = assert_ok!(SubscriberEmail::parse("Ursula.Le.Guin@example.com".into()));
+        assert_eq!(email.local_part(), "Ursula.Le.Guin");
+    }
+
     #[quickcheck_macros::quickcheck]
     fn valid_emails_are_parsed_successfully(valid_email: ValidEmailFixture) -> bool {
         SubscriberEmail::parse(valid_email.0).is_ok()
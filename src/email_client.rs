@@ -1,13 +1,83 @@
+use crate::circuit_breaker::{CircuitBreaker, CircuitOpen, CircuitState};
 use crate::domain::SubscriberEmail;
+use crate::outbound_rate_limit::{
+    AcquireError, DailyCapReached, OutboundRateLimiter, ThrottleMetrics,
+};
+use crate::telemetry::trace_context_headers;
 use reqwest::Client;
 use secrecy::{ExposeSecret, Secret};
 use serde;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+// clones share the same rate limiter, so the API and the delivery workers stay within
+// the provider limits together
+#[derive(Clone)]
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    rate_limiter: Arc<OutboundRateLimiter>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    deliveries: Arc<DeliveryCounters>,
+}
+
+// what an email is for, so deliveries can be told apart in the metrics
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmailKind {
+    Transactional,
+    Newsletter,
+    Test,
+}
+
+impl EmailKind {
+    pub const ALL: [EmailKind; 3] = [Self::Transactional, Self::Newsletter, Self::Test];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Transactional => "transactional",
+            Self::Newsletter => "newsletter",
+            Self::Test => "test",
+        }
+    }
+}
+
+#[derive(Default)]
+struct DeliveryCounters {
+    sent: [AtomicU64; 3],
+    failed: [AtomicU64; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DeliveryMetrics {
+    pub kind: EmailKind,
+    pub sent: u64,
+    // emails the provider did not accept, emails held back by the breaker or the cap are not counted
+    pub failed: u64,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error(transparent)]
+    CircuitOpen(#[from] CircuitOpen),
+    #[error(transparent)]
+    DailyCapReached(#[from] DailyCapReached),
+    // the daily cap could not be checked, the email was not sent
+    #[error("Failed to count the emails sent today.")]
+    DailyCountFailed(#[source] sqlx::Error),
+    #[error(transparent)]
+    RequestFailed(#[from] reqwest::Error),
+}
+
+impl From<AcquireError> for SendEmailError {
+    fn from(e: AcquireError) -> Self {
+        match e {
+            AcquireError::DailyCapReached(e) => Self::DailyCapReached(e),
+            AcquireError::CountFailed(e) => Self::DailyCountFailed(e),
+        }
+    }
 }
 
 #[derive(serde::Serialize)]
@@ -18,6 +88,21 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader],
+}
+
+#[derive(serde::Deserialize)]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader {
+    pub name: String,
+    pub value: String,
 }
 
 impl EmailClient {
@@ -25,7 +110,9 @@ impl EmailClient {
         base_url: String,
         sender: SubscriberEmail,
         authorization_token: Secret<String>,
-        timeout: std::time::Duration,
+        timeout: Duration,
+        rate_limiter: OutboundRateLimiter,
+        circuit_breaker: CircuitBreaker,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
 
@@ -34,16 +121,92 @@ impl EmailClient {
             base_url,
             sender,
             authorization_token,
+            rate_limiter: Arc::new(rate_limiter),
+            circuit_breaker: Arc::new(circuit_breaker),
+            deliveries: Arc::default(),
         }
     }
 
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker.state()
+    }
+
+    pub fn throttle_metrics(&self) -> ThrottleMetrics {
+        self.rate_limiter.metrics()
+    }
+
+    pub fn delivery_metrics(&self) -> Vec<DeliveryMetrics> {
+        EmailKind::ALL
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| DeliveryMetrics {
+                kind: *kind,
+                sent: self.deliveries.sent[i].load(Ordering::Relaxed),
+                failed: self.deliveries.failed[i].load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
     pub async fn send_email(
         &self,
+        kind: EmailKind,
         recipient: SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), SendEmailError> {
+        self.send_email_with_headers(kind, recipient, subject, html_content, text_content, &[])
+            .await?;
+        Ok(())
+    }
+
+    // returns the id the provider gave to the email, if it reported one
+    pub async fn send_email_with_headers(
+        &self,
+        kind: EmailKind,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<Option<String>, SendEmailError> {
+        self.circuit_breaker.try_acquire()?;
+        let slot = self.rate_limiter.acquire().await?;
+
+        let outcome = self
+            .post_email(recipient, subject, html_content, text_content, headers)
+            .await;
+        match &outcome {
+            // the provider is up, it only refused this one email
+            Err(e) if is_provider_failure(e) => self.circuit_breaker.record_failure(),
+            _ => self.circuit_breaker.record_success(),
+        }
+        let counters = match outcome {
+            Ok(_) => &self.deliveries.sent,
+            Err(_) => &self.deliveries.failed,
+        };
+        if outcome.is_err() {
+            if let Err(e) = self.rate_limiter.refund(slot).await {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to give back the daily cap slot of an email that was not sent."
+                );
+            }
+        }
+        counters[kind as usize].fetch_add(1, Ordering::Relaxed);
+
+        Ok(outcome?)
+    }
+
+    async fn post_email(
+        &self,
+        recipient: SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader],
+    ) -> Result<Option<String>, reqwest::Error> {
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
             from: self.sender.as_ref(),
@@ -51,30 +214,54 @@ impl EmailClient {
             subject,
             html_body: html_content,
             text_body: text_content,
+            headers,
         };
-        self.http_client
+        let response = self
+            .http_client
             .post(&url)
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
+            .headers(trace_context_headers())
             .json(&request_body)
             .send()
             .await?
             .error_for_status()?;
-        Ok(())
+        // the email was accepted, a response we cannot read only loses the id
+        let message_id = response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .map(|r| r.message_id);
+        Ok(message_id)
     }
 }
 
+// timeouts, connection errors and server errors, as opposed to emails the provider rejected
+fn is_provider_failure(e: &reqwest::Error) -> bool {
+    e.status().is_none_or(|status| status.is_server_error())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::circuit_breaker::{CircuitBreaker, CircuitState};
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, EmailKind, SendEmailError};
+    use crate::outbound_rate_limit::OutboundRateLimiter;
+    use crate::telemetry::{get_subscriber, RedactionPolicy};
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
+    use opentelemetry::global;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
     use secrecy::Secret;
+    use std::collections::HashMap;
+    use tracing::Instrument;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
     use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Match, Mock, MockServer, Request, ResponseTemplate};
 
@@ -117,6 +304,8 @@ mod tests {
             email(),
             Secret::new(Faker.fake()),
             std::time::Duration::from_millis(200),
+            OutboundRateLimiter::new(None, None),
+            CircuitBreaker::new(2, std::time::Duration::from_secs(30)),
         )
     }
 
@@ -140,7 +329,13 @@ mod tests {
 
         // act
         let _ = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         // assert
@@ -158,7 +353,13 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_ok!(outcome);
@@ -176,7 +377,13 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_err!(outcome);
@@ -196,9 +403,158 @@ mod tests {
             .await;
 
         let outcome = email_client
-            .send_email(email(), &subject(), &content(), &content())
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
             .await;
 
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn send_email_fails_fast_once_the_server_keeps_failing() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        // the third email never reaches the server
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(
+                    EmailKind::Transactional,
+                    email(),
+                    &subject(),
+                    &content(),
+                    &content(),
+                )
+                .await;
+            assert_err!(outcome);
+        }
+        let outcome = email_client
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .await;
+
+        assert!(matches!(outcome, Err(SendEmailError::CircuitOpen(_))));
+        assert_eq!(email_client.circuit_state(), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn emails_rejected_by_the_server_do_not_open_the_circuit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            let outcome = email_client
+                .send_email(
+                    EmailKind::Transactional,
+                    email(),
+                    &subject(),
+                    &content(),
+                    &content(),
+                )
+                .await;
+            assert!(matches!(outcome, Err(SendEmailError::RequestFailed(_))));
+        }
+
+        assert_eq!(email_client.circuit_state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn deliveries_are_counted_by_kind() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .mount(&mock_server)
+            .await;
+
+        for kind in [
+            EmailKind::Newsletter,
+            EmailKind::Newsletter,
+            EmailKind::Test,
+        ] {
+            let _ = email_client
+                .send_email(kind, email(), &subject(), &content(), &content())
+                .await;
+        }
+
+        let metrics = email_client.delivery_metrics();
+        let newsletter = metrics
+            .iter()
+            .find(|m| m.kind == EmailKind::Newsletter)
+            .unwrap();
+        let test = metrics.iter().find(|m| m.kind == EmailKind::Test).unwrap();
+        assert_eq!((newsletter.sent, newsletter.failed), (2, 0));
+        assert_eq!((test.sent, test.failed), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn the_trace_of_an_incoming_request_is_passed_on_to_the_provider() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        Mock::given(header_exists("traceparent"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            Some(tracer),
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        // the parent a caller sent in its own 'traceparent' header
+        let incoming = HashMap::from([(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )]);
+        let span = tracing::info_span!("Handle an incoming request");
+        span.set_parent(global::get_text_map_propagator(|p| p.extract(&incoming)));
+        email_client
+            .send_email(
+                EmailKind::Transactional,
+                email(),
+                &subject(),
+                &content(),
+                &content(),
+            )
+            .instrument(span)
+            .await
+            .unwrap();
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let traceparent = request.headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+    }
 }
@@ -0,0 +1,171 @@
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::domain::SubscriberEmail;
+
+// the providers most subscribers use, misspellings of these get a suggestion
+const COMMON_PROVIDERS: &[&str] = &[
+    "aol.com",
+    "gmail.com",
+    "gmx.com",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.com",
+    "yandex.com",
+];
+
+// checks that the domain of an address can receive email, catching typos before they bounce
+pub struct EmailDomainChecker {
+    resolver: Option<TokioAsyncResolver>,
+}
+
+impl EmailDomainChecker {
+    pub fn disabled() -> Self {
+        Self { resolver: None }
+    }
+
+    pub fn new(resolver_address: SocketAddr, timeout: Duration) -> Self {
+        let name_servers = NameServerConfigGroup::from_ips_clear(
+            &[resolver_address.ip()],
+            resolver_address.port(),
+            true,
+        );
+        let mut options = ResolverOpts::default();
+        options.timeout = timeout;
+        options.attempts = 1;
+        let resolver = TokioAsyncResolver::tokio(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            options,
+        );
+
+        Self {
+            resolver: Some(resolver),
+        }
+    }
+
+    // an unreachable resolver lets the address through, subscriptions must not depend on it
+    #[tracing::instrument(name = "Check the domain of a subscriber email", skip(self))]
+    pub async fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let Some(resolver) = &self.resolver else {
+            return Ok(());
+        };
+
+        match accepts_email(resolver, email.domain()).await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                let mut message = format!("{} cannot receive email.", email.domain());
+                if let Some(suggestion) = suggest_domain(email.domain()) {
+                    message.push_str(&format!(
+                        " Did you mean {}@{suggestion}?",
+                        email.local_part()
+                    ));
+                }
+                Err(message)
+            }
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "Failed to resolve the domain of an email.");
+                Ok(())
+            }
+        }
+    }
+}
+
+// a domain without MX records still receives email on its A/AAAA records,
+// unless it publishes a null MX to say it accepts none
+async fn accepts_email(resolver: &TokioAsyncResolver, domain: &str) -> Result<bool, ResolveError> {
+    // fully qualified, so that no search domain is ever appended
+    let fqdn = format!("{domain}.");
+
+    match resolver.mx_lookup(fqdn.as_str()).await {
+        Ok(mx) => return Ok(mx.iter().any(|mx| !mx.exchange().is_root())),
+        Err(e) if !is_no_records(&e) => return Err(e),
+        Err(_) => {}
+    }
+    match resolver.lookup_ip(fqdn.as_str()).await {
+        Ok(ips) => Ok(ips.iter().next().is_some()),
+        Err(e) if is_no_records(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_no_records(e: &ResolveError) -> bool {
+    matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
+
+pub fn suggest_domain(domain: &str) -> Option<&'static str> {
+    // providers are close to each other, 'gmail.com' is one edit away from 'mail.com'
+    if COMMON_PROVIDERS.contains(&domain) {
+        return None;
+    }
+
+    COMMON_PROVIDERS
+        .iter()
+        .map(|provider| (*provider, edit_distance(domain, provider)))
+        .filter(|(_, distance)| *distance <= 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(provider, _)| provider)
+}
+
+// optimal string alignment distance: swapping two adjacent letters, the most common typo,
+// counts as a single edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, suggest_domain};
+    use claims::{assert_none, assert_some_eq};
+
+    #[test]
+    fn swapped_letters_count_as_a_single_edit() {
+        assert_eq!(edit_distance("gmial.com", "gmail.com"), 1);
+        assert_eq!(edit_distance("gmail.com", "gmail.com"), 0);
+        assert_eq!(edit_distance("hotmial.co", "hotmail.com"), 2);
+    }
+
+    #[test]
+    fn common_misspellings_get_a_suggestion() {
+        assert_some_eq!(suggest_domain("gmial.com"), "gmail.com");
+        assert_some_eq!(suggest_domain("yaho.com"), "yahoo.com");
+        assert_some_eq!(suggest_domain("outlok.com"), "outlook.com");
+    }
+
+    #[test]
+    fn correct_and_unrelated_domains_get_no_suggestion() {
+        assert_none!(suggest_domain("gmail.com"));
+        assert_none!(suggest_domain("example.org"));
+    }
+}
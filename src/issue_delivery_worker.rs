@@ -0,0 +1,410 @@
+use crate::configuration::{Settings, TrackingSettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailKind, SendEmailError};
+use crate::merge_fields::{substitute_merge_fields, ContentFormat, MergeFields};
+use crate::newsletter_html::{prepare_tracked_newsletter_html, NewsletterHtmlError};
+use crate::routes::{list_unsubscribe_headers, unsubscribe_url};
+use crate::shutdown::pause;
+use crate::startup::get_connection_pool;
+use crate::suppression_list::is_suppressed;
+use crate::tracking::DeliveryTracking;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+    // the provider quota is used up, the task stays in the queue until it resets
+    QuotaExhausted { resumes_at: DateTime<Utc> },
+    // the circuit breaker is open, the task stays in the queue until the provider recovers
+    ProviderUnavailable,
+}
+
+pub struct NewsletterIssue {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub slug: String,
+    pub is_public: bool,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct RenderedIssue {
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub clipped_by: Vec<&'static str>,
+}
+
+impl NewsletterIssue {
+    // produces the email exactly as it is handed over to the 'EmailClient'.
+    // merge fields are resolved before sanitizing, so that links they produce are checked too
+    pub fn render(
+        &self,
+        base_url: &str,
+        merge_fields: &MergeFields,
+        tracking: Option<&DeliveryTracking>,
+    ) -> Result<RenderedIssue, NewsletterHtmlError> {
+        let html_content =
+            substitute_merge_fields(&self.html_content, merge_fields, ContentFormat::Html);
+        let text_content =
+            substitute_merge_fields(&self.text_content, merge_fields, ContentFormat::Text);
+        let rewrite_link = tracking
+            .filter(|tracking| tracking.clicks)
+            .map(DeliveryTracking::link_rewriter);
+        let mut html = prepare_tracked_newsletter_html(&html_content, base_url, rewrite_link)?;
+        if let Some(tracking) = tracking.filter(|tracking| tracking.opens) {
+            html.html.push_str(&tracking.open_pixel());
+        }
+
+        // only public issues can be read in the archive
+        let (html_body, text_body) = if self.is_public {
+            let web_view_url = format!("{base_url}/archive/{}", self.slug);
+            (
+                format!(
+                    "<p><a href=\"{web_view_url}\">View in browser</a></p>{}",
+                    html.html
+                ),
+                format!("View in browser: {web_view_url}\n\n{text_content}"),
+            )
+        } else {
+            (html.html, text_content)
+        };
+
+        Ok(RenderedIssue {
+            subject: substitute_merge_fields(&self.title, merge_fields, ContentFormat::Text),
+            html_body,
+            text_body,
+            clipped_by: html.clipped_by,
+        })
+    }
+}
+
+// returns once 'shutdown' is cancelled, after the task in progress is done
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    worker_loop(
+        &connection_pool,
+        email_client,
+        configuration.application.base_url,
+        configuration.tracking,
+        shutdown,
+    )
+    .await;
+    connection_pool.close().await;
+    Ok(())
+}
+
+async fn worker_loop(
+    pool: &PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    tracking: TrackingSettings,
+    shutdown: CancellationToken,
+) {
+    while !shutdown.is_cancelled() {
+        match try_execute_task(pool, &email_client, &base_url, &tracking).await {
+            Ok(ExecutionOutcome::EmptyQueue) => {
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(ExecutionOutcome::QuotaExhausted { resumes_at }) => {
+                tracing::warn!(%resumes_at, "Pausing deliveries until the email quota resets.");
+                let duration = (resumes_at - Utc::now()).to_std().unwrap_or_default();
+                pause(duration, &shutdown).await;
+            }
+            Ok(ExecutionOutcome::ProviderUnavailable) => {
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+        }
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        newsletter_issue_id=tracing::field::Empty,
+        subscriber_email=tracing::field::Empty
+    ),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    tracking: &TrackingSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let task = dequeue_task(pool).await?;
+    let Some((mut transaction, issue_id, email)) = task else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+
+    Span::current()
+        .record("newsletter_issue_id", display(issue_id))
+        .record("subscriber_email", display(&email));
+
+    // the subscriber may have unsubscribed or been suppressed since the task was enqueued
+    let Some(merge_fields) = get_merge_fields(pool, &email, base_url).await? else {
+        tracing::info!("Skipping a subscriber who is no longer confirmed.");
+        delete_task(transaction, issue_id, &email).await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    };
+
+    match SubscriberEmail::parse(email.clone()) {
+        Ok(recipient) if is_suppressed(pool, &recipient).await? => {
+            tracing::info!("Skipping a subscriber whose address is on the suppression list.");
+        }
+        Ok(recipient) => {
+            let issue = get_issue(pool, issue_id)
+                .await?
+                .context("The issue of a delivery task does not exist.")?;
+            let delivery_id = Uuid::new_v4();
+            let delivery_tracking = (tracking.enabled && (issue.track_opens || issue.track_clicks))
+                .then(|| DeliveryTracking {
+                    delivery_id,
+                    base_url: base_url.to_owned(),
+                    hmac_secret: tracking.hmac_secret.clone(),
+                    opens: issue.track_opens,
+                    clicks: issue.track_clicks,
+                });
+            // the content was validated when the issue was created, so this should not fail
+            let rendered = issue.render(base_url, &merge_fields, delivery_tracking.as_ref())?;
+
+            let headers = merge_fields
+                .unsubscribe_url
+                .as_deref()
+                .map(list_unsubscribe_headers)
+                .unwrap_or_default();
+
+            let (status, message_id) = match email_client
+                .send_email_with_headers(
+                    EmailKind::Newsletter,
+                    recipient,
+                    &rendered.subject,
+                    &rendered.html_body,
+                    &rendered.text_body,
+                    &headers,
+                )
+                .await
+            {
+                Ok(message_id) => ("sent", message_id),
+                Err(SendEmailError::CircuitOpen(_)) => {
+                    transaction
+                        .rollback()
+                        .await
+                        .context("Failed to release a delivery task.")?;
+                    return Ok(ExecutionOutcome::ProviderUnavailable);
+                }
+                Err(SendEmailError::DailyCapReached(e)) => {
+                    transaction
+                        .rollback()
+                        .await
+                        .context("Failed to release a delivery task.")?;
+                    return Ok(ExecutionOutcome::QuotaExhausted {
+                        resumes_at: e.resets_at,
+                    });
+                }
+                // nothing was sent, the task is released and retried
+                Err(e @ SendEmailError::DailyCountFailed(_)) => return Err(e.into()),
+                Err(e) => {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to deliver issue to a confirmed subscriber. Skipping."
+                    );
+                    ("failed", None)
+                }
+            };
+            record_delivery(
+                &mut transaction,
+                delivery_id,
+                issue_id,
+                &email,
+                status,
+                message_id.as_deref(),
+            )
+            .await?;
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Skipping a confirmed subscriber. Their stored contact details are invalid."
+            );
+        }
+    }
+
+    delete_task(transaction, issue_id, &email).await?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// the row lock is held until the transaction ends, so concurrent workers never pick the same task
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let r = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, subscriber_email
+        FROM issue_delivery_queue
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    if let Some(r) = r {
+        Ok(Some((
+            transaction,
+            r.newsletter_issue_id,
+            r.subscriber_email,
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+// recorded in the transaction of the task, so a delivery is logged once it leaves the queue
+#[tracing::instrument(skip_all)]
+async fn record_delivery(
+    transaction: &mut PgTransaction,
+    delivery_id: Uuid,
+    issue_id: Uuid,
+    email: &str,
+    status: &str,
+    message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            delivery_id,
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            message_id,
+            attempted_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now())
+        "#,
+        delivery_id,
+        issue_id,
+        email,
+        status,
+        message_id
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    mut transaction: PgTransaction,
+    issue_id: Uuid,
+    email: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        issue_id,
+        email
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_issue(
+    pool: &PgPool,
+    issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, sqlx::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT title, text_content, html_content, slug, is_public, track_opens, track_clicks
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(issue)
+}
+
+// resolves the merge fields of a recipient from their 'subscriptions' row,
+// nothing is returned if they are no longer a confirmed subscriber
+#[tracing::instrument(skip_all)]
+async fn get_merge_fields(
+    pool: &PgPool,
+    email: &str,
+    base_url: &str,
+) -> Result<Option<MergeFields>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT name, subscribed_at, unsubscribe_token
+        FROM subscriptions
+        WHERE email = $1 AND status = 'confirmed'
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(subscriber.map(|s| MergeFields {
+        name: Some(s.name),
+        unsubscribe_url: Some(unsubscribe_url(base_url, &s.unsubscribe_token)),
+        subscribed_since: Some(s.subscribed_at),
+    }))
+}
+
+// fan out a published issue: one delivery task for every confirmed subscriber.
+// unsubscribed and suppressed addresses are left out
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE status = 'confirmed'
+        "#,
+        newsletter_issue_id,
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
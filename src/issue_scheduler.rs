@@ -0,0 +1,79 @@
+use crate::configuration::Settings;
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::shutdown::pause;
+use crate::startup::get_connection_pool;
+use sqlx::{Executor, PgPool};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+
+pub enum SchedulerOutcome {
+    IssueEnqueued,
+    NoDueIssue,
+}
+
+// returns once 'shutdown' is cancelled, after the issue in progress is enqueued
+pub async fn run_scheduler_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    scheduler_loop(&connection_pool, shutdown).await;
+    connection_pool.close().await;
+    Ok(())
+}
+
+async fn scheduler_loop(pool: &PgPool, shutdown: CancellationToken) {
+    while !shutdown.is_cancelled() {
+        match try_enqueue_due_issue(pool).await {
+            Ok(SchedulerOutcome::NoDueIssue) => {
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(SchedulerOutcome::IssueEnqueued) => {}
+        }
+    }
+}
+
+// publishes the oldest scheduled issue whose time has come.
+// the issue row stays locked until its delivery tasks are committed, and other instances skip
+// locked rows: once committed, 'published_at' is set and the issue is never picked up again
+#[tracing::instrument(skip_all, fields(newsletter_issue_id=tracing::field::Empty), err)]
+pub async fn try_enqueue_due_issue(pool: &PgPool) -> Result<SchedulerOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let due_issue = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE published_at IS NULL AND scheduled_at <= now()
+        ORDER BY scheduled_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some(due_issue) = due_issue else {
+        return Ok(SchedulerOutcome::NoDueIssue);
+    };
+    let issue_id = due_issue.newsletter_issue_id;
+    Span::current().record("newsletter_issue_id", display(issue_id));
+
+    let query = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now()
+        WHERE newsletter_issue_id = $1
+        "#,
+        issue_id
+    );
+    transaction.execute(query).await?;
+    enqueue_delivery_tasks(&mut transaction, issue_id).await?;
+    transaction.commit().await?;
+
+    Ok(SchedulerOutcome::IssueEnqueued)
+}
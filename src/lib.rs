@@ -1,6 +1,23 @@
+pub mod authentication;
+pub mod bot_protection;
+pub mod circuit_breaker;
+pub mod cli;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod email_domain_check;
+pub mod issue_delivery_worker;
+pub mod issue_scheduler;
+pub mod merge_fields;
+pub mod metrics;
+pub mod migrations;
+pub mod newsletter_html;
+pub mod outbound_rate_limit;
+pub mod outbox_dispatcher;
+pub mod rate_limiting;
 pub mod routes;
+pub mod shutdown;
 pub mod startup;
+pub mod suppression_list;
 pub mod telemetry;
+pub mod tracking;
@@ -1,15 +1,146 @@
-use zero2prod_newsletter::configuration::get_configuration;
-use zero2prod_newsletter::startup::Application;
-use zero2prod_newsletter::telemetry::{get_subscriber, init_subscriber};
+use sqlx::{Connection, PgConnection};
+use std::fmt::{Debug, Display};
+use tokio::task::{JoinError, JoinHandle};
+use tokio_util::sync::CancellationToken;
+use zero2prod_newsletter::cli::{Command, MigrateCommand};
+use zero2prod_newsletter::configuration::{get_configuration, DatabaseSettings, Settings};
+use zero2prod_newsletter::issue_delivery_worker::run_worker_until_stopped;
+use zero2prod_newsletter::issue_scheduler::run_scheduler_until_stopped;
+use zero2prod_newsletter::migrations::{migration_status, run_migrations};
+use zero2prod_newsletter::outbox_dispatcher::run_dispatcher_until_stopped;
+use zero2prod_newsletter::shutdown::shutdown_signal;
+use zero2prod_newsletter::startup::{get_connection_pool, Application};
+use zero2prod_newsletter::telemetry::{
+    get_subscriber, init_subscriber, init_tracing_export, shutdown_tracing_export, LogFilter,
+};
 
 #[tokio::main]
-async fn main() -> Result<(), std::io::Error> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+async fn main() -> anyhow::Result<()> {
+    let command = Command::parse(std::env::args().skip(1))?;
+    let configuration = get_configuration().expect("Failed to read configuration.");
+    let tracer = init_tracing_export(
+        "zero2prod",
+        &configuration.telemetry,
+        configuration.redaction.policy(),
+    )?;
+    let (subscriber, log_filter) = get_subscriber(
+        "zero2prod".into(),
+        "info".into(),
+        std::io::stdout,
+        configuration.redaction.policy(),
+        tracer,
+    );
     init_subscriber(subscriber);
 
-    let configuration = get_configuration().expect("Failed to read configuration.");
-    let application = Application::build(configuration).await?;
-    application.run_until_stopped().await?;
+    let outcome = match command {
+        Command::Serve => serve(configuration, log_filter).await,
+        Command::Migrate(MigrateCommand::Up) => {
+            run_migrations(&configuration.database).await.map(|_| ())
+        }
+        Command::Migrate(MigrateCommand::Status) => {
+            print_migration_status(&configuration.database).await
+        }
+    };
+    shutdown_tracing_export();
+
+    outcome
+}
+
+async fn serve(configuration: Settings, log_filter: LogFilter) -> anyhow::Result<()> {
+    // build an 'EmailClient' using 'configuration', its daily cap is counted through 'email_pool'
+    let email_pool = get_connection_pool(&configuration.database);
+    let email_client = configuration
+        .email_client
+        .clone()
+        .client(email_pool.clone());
+    let shutdown = CancellationToken::new();
+    let application = Application::build(
+        configuration.clone(),
+        email_client.clone(),
+        log_filter,
+        shutdown.clone(),
+    )
+    .await?;
+    let application_task = tokio::spawn(application.run_until_stopped());
+    let worker_task = tokio::spawn(run_worker_until_stopped(
+        configuration.clone(),
+        email_client.clone(),
+        shutdown.clone(),
+    ));
+    let outbox_task = tokio::spawn(run_dispatcher_until_stopped(
+        configuration.clone(),
+        email_client,
+        shutdown.clone(),
+    ));
+    let scheduler_task = tokio::spawn(run_scheduler_until_stopped(configuration, shutdown.clone()));
+
+    // a shutdown starts on a signal or as soon as any of the tasks stops, the process exits
+    // once all of them have finished their work in progress
+    tokio::join!(
+        async {
+            tokio::select! {
+                _ = shutdown_signal() => tracing::info!("Received a shutdown signal."),
+                _ = shutdown.cancelled() => {}
+            }
+            shutdown.cancel();
+        },
+        supervise("API", application_task, &shutdown),
+        supervise("Background worker", worker_task, &shutdown),
+        supervise("Issue scheduler", scheduler_task, &shutdown),
+        supervise("Outbox dispatcher", outbox_task, &shutdown),
+    );
+    // every task that sends emails has finished
+    email_pool.close().await;
+
+    Ok(())
+}
+
+async fn print_migration_status(configuration: &DatabaseSettings) -> anyhow::Result<()> {
+    let mut connection = PgConnection::connect_with(&configuration.with_db()).await?;
+    for migration in migration_status(&mut connection).await? {
+        let status = if migration.applied {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!(
+            "{} {:<8} {}",
+            migration.version, status, migration.description
+        );
+    }
 
     Ok(())
 }
+
+async fn supervise<E: Debug + Display>(
+    task_name: &str,
+    task: JoinHandle<Result<(), E>>,
+    shutdown: &CancellationToken,
+) {
+    report_exit(task_name, task.await);
+    shutdown.cancel();
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+
+// placeholders are written as '{{field}}', or '{{field|fallback}}' to provide a value
+// for recipients that do not have one
+const KNOWN_FIELDS: &[&str] = &["name", "unsubscribe_url", "subscribed_since"];
+
+// per-recipient values, missing ones are replaced by the placeholder fallback
+#[derive(Default)]
+pub struct MergeFields {
+    pub name: Option<String>,
+    pub unsubscribe_url: Option<String>,
+    pub subscribed_since: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy)]
+pub enum ContentFormat {
+    Html,
+    Text,
+}
+
+struct Placeholder<'a> {
+    // byte range of the whole placeholder, braces included
+    start: usize,
+    end: usize,
+    field: &'a str,
+    fallback: Option<&'a str>,
+}
+
+impl MergeFields {
+    fn get(&self, field: &str) -> Option<String> {
+        match field {
+            "name" => self.name.clone(),
+            "unsubscribe_url" => self.unsubscribe_url.clone(),
+            "subscribed_since" => self
+                .subscribed_since
+                .map(|date| date.format("%B %-d, %Y").to_string()),
+            _ => None,
+        }
+    }
+}
+
+// rejects content referencing fields we cannot resolve at send time
+pub fn validate_merge_fields(content: &str) -> Result<(), String> {
+    let mut unknown: Vec<&str> = placeholders(content)
+        .map(|placeholder| placeholder.field)
+        .filter(|field| !KNOWN_FIELDS.contains(field))
+        .collect();
+    unknown.sort_unstable();
+    unknown.dedup();
+
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unknown merge fields: {}. Supported fields are: {}.",
+            unknown.join(", "),
+            KNOWN_FIELDS.join(", ")
+        ))
+    }
+}
+
+pub fn substitute_merge_fields(
+    content: &str,
+    fields: &MergeFields,
+    format: ContentFormat,
+) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut last = 0;
+
+    for placeholder in placeholders(content) {
+        output.push_str(&content[last..placeholder.start]);
+        // recipient values are escaped, fallbacks are part of the authored content
+        match fields.get(placeholder.field) {
+            Some(value) => match format {
+                ContentFormat::Html => output.push_str(&encode_minimal(&value)),
+                ContentFormat::Text => output.push_str(&value),
+            },
+            None => output.push_str(placeholder.fallback.unwrap_or_default()),
+        }
+        last = placeholder.end;
+    }
+    output.push_str(&content[last..]);
+
+    output
+}
+
+fn placeholders(content: &str) -> impl Iterator<Item = Placeholder<'_>> {
+    let mut cursor = 0;
+
+    std::iter::from_fn(move || {
+        let start = cursor + content[cursor..].find("{{")?;
+        // an unclosed placeholder is left as it is
+        let inner_end = start + 2 + content[start + 2..].find("}}")?;
+        let end = inner_end + 2;
+        cursor = end;
+
+        let inner = &content[start + 2..inner_end];
+        let (field, fallback) = match inner.split_once('|') {
+            Some((field, fallback)) => (field.trim(), Some(fallback.trim())),
+            None => (inner.trim(), None),
+        };
+
+        Some(Placeholder {
+            start,
+            end,
+            field,
+            fallback,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{substitute_merge_fields, validate_merge_fields, ContentFormat, MergeFields};
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok};
+
+    fn fields() -> MergeFields {
+        MergeFields {
+            name: Some("Ursula".into()),
+            unsubscribe_url: Some("https://example.com/unsubscribe?token=abc".into()),
+            subscribed_since: Some(Utc.with_ymd_and_hms(2024, 4, 2, 0, 0, 0).unwrap()),
+        }
+    }
+
+    #[test]
+    fn known_fields_are_replaced() {
+        let content = "Hi {{name}}, reader since {{ subscribed_since }}. {{unsubscribe_url}}";
+
+        let output = substitute_merge_fields(content, &fields(), ContentFormat::Text);
+
+        assert_eq!(
+            output,
+            "Hi Ursula, reader since April 2, 2024. https://example.com/unsubscribe?token=abc"
+        );
+    }
+
+    #[test]
+    fn the_fallback_is_used_for_missing_values() {
+        let content = "Hi {{name|there}}!";
+
+        let output = substitute_merge_fields(content, &MergeFields::default(), ContentFormat::Text);
+
+        assert_eq!(output, "Hi there!");
+    }
+
+    #[test]
+    fn missing_values_without_fallback_are_left_empty() {
+        let content = "Hi {{name}}!";
+
+        let output = substitute_merge_fields(content, &MergeFields::default(), ContentFormat::Text);
+
+        assert_eq!(output, "Hi !");
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_content() {
+        let content = r#"<a href="{{unsubscribe_url}}">Unsubscribe</a>"#;
+
+        let output = substitute_merge_fields(content, &fields(), ContentFormat::Html);
+
+        assert_eq!(
+            output,
+            r#"<a href="https://example.com/unsubscribe?token=abc">Unsubscribe</a>"#
+        );
+        let fields = MergeFields {
+            name: Some("<b>Ursula</b>".into()),
+            ..MergeFields::default()
+        };
+        assert_eq!(
+            substitute_merge_fields("{{name}}", &fields, ContentFormat::Html),
+            "&lt;b&gt;Ursula&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_left_untouched() {
+        let content = "Hi {{name";
+
+        let output = substitute_merge_fields(content, &fields(), ContentFormat::Text);
+
+        assert_eq!(output, "Hi {{name");
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert_err!(validate_merge_fields("Hi {{first_name}}"));
+        assert_err!(validate_merge_fields("Hi {{first_name|there}}"));
+    }
+
+    #[test]
+    fn each_unknown_field_is_reported_once() {
+        let error = validate_merge_fields("{{first_name}} {{city}} {{first_name}}").unwrap_err();
+
+        assert!(error.starts_with("Unknown merge fields: city, first_name."));
+    }
+
+    #[test]
+    fn known_fields_are_accepted() {
+        assert_ok!(validate_merge_fields(
+            "Hi {{name|there}}, {{subscribed_since}} {{unsubscribe_url}}"
+        ));
+        assert_ok!(validate_merge_fields("No placeholders at all"));
+    }
+}
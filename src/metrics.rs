@@ -0,0 +1,236 @@
+use crate::email_client::EmailClient;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::PgPool;
+use std::time::Instant;
+
+// each 'Application' owns its registry, so several of them can live in one process
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    emails_sent: IntCounterVec,
+    emails_failed: IntCounterVec,
+    subscriptions: IntCounterVec,
+    queue_depth: IntGaugeVec,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SubscriptionStage {
+    Created,
+    Confirmed,
+    Unsubscribed,
+}
+
+impl SubscriptionStage {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("zero2prod".into()), None)?;
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests served."),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to serve HTTP requests.",
+            ),
+            &["method", "route"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Connections held by the database pool.",
+            ),
+            &["state"],
+        )?;
+        let emails_sent = IntCounterVec::new(
+            Opts::new("emails_sent_total", "Emails accepted by the provider."),
+            &["kind"],
+        )?;
+        let emails_failed = IntCounterVec::new(
+            Opts::new("emails_failed_total", "Emails the provider did not accept."),
+            &["kind"],
+        )?;
+        let subscriptions = IntCounterVec::new(
+            Opts::new(
+                "subscriptions_total",
+                "Subscribers reaching each stage of the funnel.",
+            ),
+            &["stage"],
+        )?;
+        let queue_depth = IntGaugeVec::new(
+            Opts::new("queue_depth", "Emails waiting to be sent."),
+            &["queue"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(emails_sent.clone()))?;
+        registry.register(Box::new(emails_failed.clone()))?;
+        registry.register(Box::new(subscriptions.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            emails_sent,
+            emails_failed,
+            subscriptions,
+            queue_depth,
+        })
+    }
+
+    pub fn record_subscription(&self, stage: SubscriptionStage) {
+        self.subscriptions
+            .with_label_values(&[stage.as_str()])
+            .inc();
+    }
+
+    fn observe_http_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration
+            .with_label_values(&[method, route])
+            .observe(seconds);
+    }
+
+    // the values owned by other components are sampled when the metrics are scraped
+    #[tracing::instrument(name = "Render metrics", skip_all)]
+    pub async fn render(&self, pool: &PgPool, email_client: &EmailClient) -> String {
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(pool.size() as i64 - idle);
+
+        // the email client counts deliveries itself, as they also happen in the background workers
+        for delivery in email_client.delivery_metrics() {
+            let kind = delivery.kind.as_str();
+            catch_up(&self.emails_sent, kind, delivery.sent);
+            catch_up(&self.emails_failed, kind, delivery.failed);
+        }
+
+        match queue_depths(pool).await {
+            Ok((issue_deliveries, outbox_messages)) => {
+                self.queue_depth
+                    .with_label_values(&["issue_delivery"])
+                    .set(issue_deliveries);
+                self.queue_depth
+                    .with_label_values(&["email_outbox"])
+                    .set(outbox_messages);
+            }
+            // the previous depths are kept rather than failing the whole scrape
+            Err(e) => tracing::warn!(error.message = %e, "Failed to measure the queue depths."),
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics.");
+        String::from_utf8(buffer).expect("Metrics are always valid UTF-8.")
+    }
+}
+
+// brings a counter up to a total kept elsewhere, counters can only be incremented
+fn catch_up(counter: &IntCounterVec, kind: &str, total: u64) {
+    let counter = counter.with_label_values(&[kind]);
+    counter.inc_by(total.saturating_sub(counter.get()));
+}
+
+async fn queue_depths(pool: &PgPool) -> Result<(i64, i64), sqlx::Error> {
+    let issue_deliveries =
+        sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+            .fetch_one(pool)
+            .await?;
+    let outbox_messages = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!" FROM email_outbox WHERE status = 'pending'"#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((issue_deliveries, outbox_messages))
+}
+
+// requests are labelled with their route pattern rather than their path, so ids and tokens
+// do not create a new series each
+pub async fn track_http_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let started_at = Instant::now();
+
+    let result = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        // requests rejected by an inner middleware, for credentials or rate limits, end as errors
+        let status = match &result {
+            Ok(response) => response.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        metrics.observe_http_request(
+            &method,
+            &route,
+            status.as_u16(),
+            started_at.elapsed().as_secs_f64(),
+        );
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{catch_up, Metrics, SubscriptionStage};
+
+    #[test]
+    fn counters_catch_up_with_totals_kept_elsewhere() {
+        let metrics = Metrics::new().unwrap();
+
+        catch_up(&metrics.emails_sent, "newsletter", 3);
+        catch_up(&metrics.emails_sent, "newsletter", 5);
+
+        assert_eq!(
+            metrics.emails_sent.with_label_values(&["newsletter"]).get(),
+            5
+        );
+    }
+
+    #[test]
+    fn funnel_stages_are_counted_separately() {
+        let metrics = Metrics::new().unwrap();
+
+        metrics.record_subscription(SubscriptionStage::Created);
+        metrics.record_subscription(SubscriptionStage::Created);
+        metrics.record_subscription(SubscriptionStage::Confirmed);
+
+        let count = |stage| metrics.subscriptions.with_label_values(&[stage]).get();
+        assert_eq!(count("created"), 2);
+        assert_eq!(count("confirmed"), 1);
+        assert_eq!(count("unsubscribed"), 0);
+    }
+}
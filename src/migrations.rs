@@ -0,0 +1,87 @@
+use crate::configuration::DatabaseSettings;
+use anyhow::Context;
+use sqlx::migrate::Migrator;
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+
+// the migrations are embedded at compile time, so a binary knows which schema it expects
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+// applies the pending migrations and returns their versions. concurrent instances are
+// serialised by the lock sqlx takes on the database
+#[tracing::instrument(name = "Run database migrations", skip_all)]
+pub async fn run_migrations(configuration: &DatabaseSettings) -> Result<Vec<i64>, anyhow::Error> {
+    let mut connection = PgConnection::connect_with(&configuration.with_db())
+        .await
+        .context("Failed to connect to Postgres.")?;
+    // a migration may legitimately run longer than the statements of the application
+    connection
+        .execute("SET statement_timeout = 0")
+        .await
+        .context("Failed to lift the statement timeout.")?;
+
+    let pending: Vec<i64> = migration_status(&mut connection)
+        .await
+        .context("Failed to read the applied migrations.")?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| migration.version)
+        .collect();
+    MIGRATOR
+        .run(&mut connection)
+        .await
+        .context("Failed to run the migrations.")?;
+
+    tracing::info!(applied = ?pending, "The database schema is up to date.");
+    Ok(pending)
+}
+
+// every migration this binary ships with, in order
+pub async fn migration_status(
+    connection: &mut PgConnection,
+) -> Result<Vec<MigrationStatus>, sqlx::Error> {
+    let applied = applied_migrations(connection).await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+        })
+        .collect())
+}
+
+// versions this binary ships with that have not been applied to the database yet
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<i64>, sqlx::Error> {
+    let mut connection = pool.acquire().await?;
+
+    Ok(migration_status(&mut connection)
+        .await?
+        .into_iter()
+        .filter(|migration| !migration.applied)
+        .map(|migration| migration.version)
+        .collect())
+}
+
+async fn applied_migrations(connection: &mut PgConnection) -> Result<Vec<i64>, sqlx::Error> {
+    // a database that was never migrated has no bookkeeping table at all
+    let initialised = sqlx::query_scalar!(
+        r#"SELECT to_regclass('_sqlx_migrations') IS NOT NULL AS "initialised!""#
+    )
+    .fetch_one(&mut *connection)
+    .await?;
+    if !initialised {
+        return Ok(Vec::new());
+    }
+
+    sqlx::query_scalar!("SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(connection)
+        .await
+}
@@ -0,0 +1,221 @@
+use ammonia::{Builder, Url, UrlRelative};
+use css_inline::CSSInliner;
+use std::borrow::Cow;
+
+// email clients that truncate a message once its HTML body grows past a given size,
+// hiding the rest of the content behind a "view entire message" link
+const CLIPPING_THRESHOLDS: &[(&str, usize)] = &[("Gmail", 102 * 1024)];
+
+// css values that can execute code in legacy rendering engines
+const FORBIDDEN_STYLE_VALUES: &[&str] =
+    &["expression(", "javascript:", "behavior:", "-moz-binding"];
+
+// maps the absolute url of a link to the one sent in its place, 'None' leaves the link untouched
+pub type LinkRewriter = Box<dyn Fn(&Url) -> Option<String> + Send + Sync>;
+
+#[derive(Debug)]
+pub struct NewsletterHtml {
+    pub html: String,
+    // names of the email clients expected to clip the message because of its size
+    pub clipped_by: Vec<&'static str>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum NewsletterHtmlError {
+    #[error("The application base url is not a valid absolute url.")]
+    InvalidBaseUrl(#[source] ammonia::url::ParseError),
+    #[error("Failed to inline the stylesheets of the newsletter content.")]
+    InvalidStylesheet(#[source] css_inline::InlineError),
+}
+
+// prepares admin-submitted HTML for delivery:
+//   1. <style> rules are inlined into the 'style' attribute of the matching elements,
+//      since most email clients ignore or strip stylesheets
+//   2. scripts, event handlers and other dangerous markup are removed
+//   3. relative URLs are rewritten against the application base url
+//   4. the final size is checked against known clipping thresholds
+pub fn prepare_newsletter_html(
+    raw_html: &str,
+    base_url: &str,
+) -> Result<NewsletterHtml, NewsletterHtmlError> {
+    prepare_tracked_newsletter_html(raw_html, base_url, None)
+}
+
+// same as 'prepare_newsletter_html', with the links of the content passed through 'rewrite_link'
+#[tracing::instrument(name = "Prepare newsletter HTML content", skip(raw_html, rewrite_link))]
+pub fn prepare_tracked_newsletter_html(
+    raw_html: &str,
+    base_url: &str,
+    rewrite_link: Option<LinkRewriter>,
+) -> Result<NewsletterHtml, NewsletterHtmlError> {
+    let base_url = Url::parse(base_url).map_err(NewsletterHtmlError::InvalidBaseUrl)?;
+
+    // inlining runs first: the sanitizer drops <style> tags altogether
+    let inlined = CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .keep_link_tags(false)
+        .build()
+        .inline_fragment(raw_html, "")
+        .map_err(NewsletterHtmlError::InvalidStylesheet)?;
+
+    // the filter sees links before relative ones are resolved, so it resolves them on its own
+    let link_base_url = base_url.clone();
+    let html = Builder::default()
+        .add_generic_attributes(&["style", "align", "width", "height", "bgcolor"])
+        .add_tag_attributes("table", &["border", "cellpadding", "cellspacing"])
+        .url_relative(UrlRelative::RewriteWithBase(base_url))
+        .attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                (_, "style") => Some(sanitize_style(value)),
+                ("a", "href") => {
+                    let rewritten = rewrite_link.as_ref().and_then(|rewrite_link| {
+                        link_base_url
+                            .join(value)
+                            .ok()
+                            .filter(|url| matches!(url.scheme(), "http" | "https"))
+                            .and_then(|url| rewrite_link(&url))
+                    });
+                    Some(rewritten.map_or(value.into(), Cow::Owned))
+                }
+                _ => Some(value.into()),
+            },
+        )
+        .clean(&inlined)
+        .to_string();
+
+    let size = html.len();
+    let clipped_by: Vec<&'static str> = CLIPPING_THRESHOLDS
+        .iter()
+        .filter(|(_, threshold)| size > *threshold)
+        .map(|(client, _)| *client)
+        .collect();
+
+    if !clipped_by.is_empty() {
+        tracing::warn!(
+            html_size = size,
+            clipped_by = ?clipped_by,
+            "The newsletter HTML content exceeds the clipping threshold of some email clients."
+        );
+    }
+
+    Ok(NewsletterHtml { html, clipped_by })
+}
+
+// drops every declaration of an inline style whose value could execute code
+fn sanitize_style(style: &str) -> Cow<'_, str> {
+    let is_safe = |declaration: &str| {
+        let declaration = declaration.to_lowercase();
+        !FORBIDDEN_STYLE_VALUES
+            .iter()
+            .any(|forbidden| declaration.contains(forbidden))
+    };
+
+    if style.split(';').all(is_safe) {
+        return style.into();
+    }
+
+    style
+        .split(';')
+        .filter(|declaration| is_safe(declaration))
+        .collect::<Vec<_>>()
+        .join(";")
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prepare_newsletter_html, prepare_tracked_newsletter_html};
+    use claims::{assert_err, assert_ok};
+
+    const BASE_URL: &str = "https://newsletter.example.com";
+
+    #[test]
+    fn scripts_and_event_handlers_are_removed() {
+        let html = r#"<p onclick="steal()">Hello</p><script>alert("xss")</script>"#;
+
+        let prepared = prepare_newsletter_html(html, BASE_URL).unwrap();
+
+        assert_eq!(prepared.html, "<p>Hello</p>");
+    }
+
+    #[test]
+    fn javascript_urls_are_removed() {
+        let html = r#"<a href="javascript:alert(1)">Click me</a>"#;
+
+        let prepared = prepare_newsletter_html(html, BASE_URL).unwrap();
+
+        assert!(!prepared.html.contains("javascript:"));
+    }
+
+    #[test]
+    fn style_rules_are_inlined_into_the_matching_elements() {
+        let html = "<style>p { color: red; }</style><p>Hello</p>";
+
+        let prepared = prepare_newsletter_html(html, BASE_URL).unwrap();
+
+        assert!(!prepared.html.contains("<style>"));
+        assert!(prepared
+            .html
+            .contains(r#"<p style="color: red;">Hello</p>"#));
+    }
+
+    #[test]
+    fn dangerous_style_declarations_are_removed() {
+        let html = r#"<p style="color: red;width: expression(alert(1))">Hello</p>"#;
+
+        let prepared = prepare_newsletter_html(html, BASE_URL).unwrap();
+
+        assert!(prepared.html.contains("color: red"));
+        assert!(!prepared.html.contains("expression"));
+    }
+
+    #[test]
+    fn relative_urls_are_rewritten_against_the_base_url() {
+        let html = r#"<a href="/archive">Archive</a><img src="images/logo.png">"#;
+
+        let prepared = prepare_newsletter_html(html, BASE_URL).unwrap();
+
+        assert!(prepared
+            .html
+            .contains(r#"href="https://newsletter.example.com/archive""#));
+        assert!(prepared
+            .html
+            .contains(r#"src="https://newsletter.example.com/images/logo.png""#));
+    }
+
+    #[test]
+    fn links_are_passed_through_the_rewriter() {
+        let html = r#"<a href="/archive">Archive</a><a href="https://example.com">Out</a><a href="mailto:a@example.com">Mail</a>"#;
+        let rewrite_link = Box::new(|url: &super::Url| {
+            (url.host_str() == Some("example.com")).then(|| format!("https://t.test/?{url}"))
+        });
+
+        let prepared = prepare_tracked_newsletter_html(html, BASE_URL, Some(rewrite_link)).unwrap();
+
+        assert!(prepared
+            .html
+            .contains(r#"href="https://newsletter.example.com/archive""#));
+        assert!(prepared
+            .html
+            .contains(r#"href="https://t.test/?https://example.com/""#));
+        assert!(prepared.html.contains(r#"href="mailto:a@example.com""#));
+    }
+
+    #[test]
+    fn content_above_the_clipping_threshold_is_flagged() {
+        let small = "<p>Hello</p>";
+        let large = format!("<p>{}</p>", "a".repeat(103 * 1024));
+
+        let small = prepare_newsletter_html(small, BASE_URL).unwrap();
+        let large = prepare_newsletter_html(&large, BASE_URL).unwrap();
+
+        assert!(small.clipped_by.is_empty());
+        assert_eq!(large.clipped_by, vec!["Gmail"]);
+    }
+
+    #[test]
+    fn a_relative_base_url_is_rejected() {
+        assert_err!(prepare_newsletter_html("<p>Hello</p>", "/relative"));
+        assert_ok!(prepare_newsletter_html("<p>Hello</p>", BASE_URL));
+    }
+}
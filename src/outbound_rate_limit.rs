@@ -0,0 +1,206 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+// paces the emails handed to the provider, shared by every clone of an 'EmailClient'.
+// the pace is kept in memory, the daily count lives in Postgres: it survives restarts and
+// is shared by every instance of the application
+pub struct OutboundRateLimiter {
+    interval: Option<Duration>,
+    daily_cap: Option<DailyCap>,
+    next_slot: Mutex<Instant>,
+    throttle_waits: AtomicU64,
+    throttle_wait_micros: AtomicU64,
+    daily_cap_rejections: AtomicU64,
+}
+
+pub struct DailyCap {
+    pub limit: u32,
+    pub pool: PgPool,
+}
+
+// a send counted against the daily cap, it is given back if the email is not sent
+pub struct SendSlot {
+    day: Option<NaiveDate>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("The daily email cap has been reached, sending resumes at {resets_at}.")]
+pub struct DailyCapReached {
+    pub resets_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AcquireError {
+    #[error(transparent)]
+    DailyCapReached(#[from] DailyCapReached),
+    #[error("Failed to count the emails sent today.")]
+    CountFailed(#[from] sqlx::Error),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThrottleMetrics {
+    // sends that had to wait for a slot, and for how long in total
+    pub throttle_waits: u64,
+    pub throttle_wait_seconds: f64,
+    pub daily_cap_rejections: u64,
+}
+
+impl OutboundRateLimiter {
+    pub fn new(messages_per_second: Option<u32>, daily_cap: Option<DailyCap>) -> Self {
+        let interval = messages_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| Duration::from_secs(1) / rate);
+
+        Self {
+            interval,
+            daily_cap,
+            next_slot: Mutex::new(Instant::now()),
+            throttle_waits: AtomicU64::new(0),
+            throttle_wait_micros: AtomicU64::new(0),
+            daily_cap_rejections: AtomicU64::new(0),
+        }
+    }
+
+    // waits for the next free slot, or fails right away once the daily cap is reached
+    pub async fn acquire(&self) -> Result<SendSlot, AcquireError> {
+        let day = match &self.daily_cap {
+            Some(cap) => {
+                let now = Utc::now();
+                if !count_send(&cap.pool, now.date_naive(), cap.limit).await? {
+                    self.daily_cap_rejections.fetch_add(1, Ordering::Relaxed);
+                    return Err(DailyCapReached {
+                        resets_at: next_midnight(now),
+                    }
+                    .into());
+                }
+                Some(now.date_naive())
+            }
+            None => None,
+        };
+
+        let wait = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            let slot = next_slot.max(now);
+            if let Some(interval) = self.interval {
+                *next_slot = slot + interval;
+            }
+            slot - now
+        };
+
+        if !wait.is_zero() {
+            self.throttle_waits.fetch_add(1, Ordering::Relaxed);
+            self.throttle_wait_micros
+                .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+            tracing::debug!(
+                wait_ms = wait.as_millis() as u64,
+                "Throttling an outgoing email."
+            );
+            tokio::time::sleep(wait).await;
+        }
+
+        Ok(SendSlot { day })
+    }
+
+    // emails the provider did not accept do not use up the daily cap
+    pub async fn refund(&self, slot: SendSlot) -> Result<(), sqlx::Error> {
+        if let (Some(cap), Some(day)) = (&self.daily_cap, slot.day) {
+            uncount_send(&cap.pool, day).await?;
+        }
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> ThrottleMetrics {
+        ThrottleMetrics {
+            throttle_waits: self.throttle_waits.load(Ordering::Relaxed),
+            throttle_wait_seconds: self.throttle_wait_micros.load(Ordering::Relaxed) as f64
+                / 1_000_000.0,
+            daily_cap_rejections: self.daily_cap_rejections.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// returns 'false' once the cap is reached. the check and the increment are a single statement,
+// so concurrent senders never go past the cap together
+#[tracing::instrument(name = "Count an email against the daily cap", skip(pool))]
+async fn count_send(pool: &PgPool, day: NaiveDate, limit: u32) -> Result<bool, sqlx::Error> {
+    if limit == 0 {
+        return Ok(false);
+    }
+    let counted = sqlx::query!(
+        r#"
+        INSERT INTO email_daily_counts (day, sent)
+        VALUES ($1, 1)
+        ON CONFLICT (day) DO UPDATE SET sent = email_daily_counts.sent + 1
+        WHERE email_daily_counts.sent < $2
+        RETURNING sent
+        "#,
+        day,
+        i64::from(limit)
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(counted.is_some())
+}
+
+#[tracing::instrument(name = "Give back a send to the daily cap", skip(pool))]
+async fn uncount_send(pool: &PgPool, day: NaiveDate) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_daily_counts
+        SET sent = sent - 1
+        WHERE day = $1 AND sent > 0
+        "#,
+        day
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// providers reset their daily quotas at midnight UTC
+fn next_midnight(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + chrono::Days::new(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutboundRateLimiter;
+    use chrono::{TimeZone, Utc};
+    use claims::assert_ok;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_are_spaced_according_to_the_rate() {
+        let limiter = OutboundRateLimiter::new(Some(10), None);
+        let start = tokio::time::Instant::now();
+
+        for _ in 0..3 {
+            assert_ok!(limiter.acquire().await);
+        }
+
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.throttle_waits, 2);
+        assert!((metrics.throttle_wait_seconds - 0.2).abs() < 0.001);
+    }
+
+    #[test]
+    fn the_daily_cap_resets_at_midnight_utc() {
+        let now = Utc.with_ymd_and_hms(2026, 10, 19, 15, 30, 0).unwrap();
+
+        assert_eq!(
+            super::next_midnight(now),
+            Utc.with_ymd_and_hms(2026, 10, 20, 0, 0, 0).unwrap()
+        );
+    }
+}
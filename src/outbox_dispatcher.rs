@@ -0,0 +1,256 @@
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailKind, SendEmailError};
+use crate::shutdown::pause;
+use crate::startup::get_connection_pool;
+use crate::suppression_list::is_suppressed;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+// a message still failing after this many attempts is given up on
+const MAX_ATTEMPTS: i16 = 5;
+
+pub enum DispatchOutcome {
+    MessageProcessed,
+    EmptyOutbox,
+    // the provider quota is used up, the message stays in the outbox until it resets
+    QuotaExhausted { resumes_at: DateTime<Utc> },
+    // the circuit breaker is open, the message stays in the outbox until the provider recovers
+    ProviderUnavailable,
+}
+
+pub struct OutboxMessage<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+}
+
+// the message is only sent if the transaction commits
+#[tracing::instrument(skip_all, fields(message_id = tracing::field::Empty))]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    message: OutboxMessage<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let message_id = Uuid::new_v4();
+    Span::current().record("message_id", display(message_id));
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (
+            message_id,
+            recipient,
+            subject,
+            html_body,
+            text_body,
+            next_attempt_at,
+            created_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), now())
+        "#,
+        message_id,
+        message.recipient.as_ref(),
+        message.subject,
+        message.html_body,
+        message.text_body
+    );
+    transaction.execute(query).await?;
+
+    Ok(message_id)
+}
+
+// returns once 'shutdown' is cancelled, after the message in progress is done
+pub async fn run_dispatcher_until_stopped(
+    configuration: Settings,
+    email_client: EmailClient,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    dispatcher_loop(&connection_pool, email_client, shutdown).await;
+    connection_pool.close().await;
+    Ok(())
+}
+
+async fn dispatcher_loop(pool: &PgPool, email_client: EmailClient, shutdown: CancellationToken) {
+    while !shutdown.is_cancelled() {
+        match try_dispatch_message(pool, &email_client).await {
+            // signups wait on this loop, so an empty outbox is polled more often than the queue
+            Ok(DispatchOutcome::EmptyOutbox) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Err(_) => {
+                pause(Duration::from_secs(1), &shutdown).await;
+            }
+            Ok(DispatchOutcome::QuotaExhausted { resumes_at }) => {
+                tracing::warn!(%resumes_at, "Pausing the outbox until the email quota resets.");
+                let duration = (resumes_at - Utc::now()).to_std().unwrap_or_default();
+                pause(duration, &shutdown).await;
+            }
+            Ok(DispatchOutcome::ProviderUnavailable) => {
+                pause(Duration::from_secs(10), &shutdown).await;
+            }
+            Ok(DispatchOutcome::MessageProcessed) => {}
+        }
+    }
+}
+
+#[tracing::instrument(skip_all, fields(message_id = tracing::field::Empty), err)]
+pub async fn try_dispatch_message(
+    pool: &PgPool,
+    email_client: &EmailClient,
+) -> Result<DispatchOutcome, anyhow::Error> {
+    let Some((transaction, message)) = dequeue_message(pool).await? else {
+        return Ok(DispatchOutcome::EmptyOutbox);
+    };
+    Span::current().record("message_id", display(message.message_id));
+
+    let recipient = match SubscriberEmail::parse(message.recipient) {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            tracing::error!(error.message = %e, "Skipping an outbox message with an invalid recipient.");
+            mark_processed(transaction, message.message_id, "skipped").await?;
+            return Ok(DispatchOutcome::MessageProcessed);
+        }
+    };
+    // the address may have been suppressed since the message was written
+    if is_suppressed(pool, &recipient).await? {
+        tracing::info!("Skipping an outbox message to an address on the suppression list.");
+        mark_processed(transaction, message.message_id, "skipped").await?;
+        return Ok(DispatchOutcome::MessageProcessed);
+    }
+
+    match email_client
+        .send_email(
+            EmailKind::Transactional,
+            recipient,
+            &message.subject,
+            &message.html_body,
+            &message.text_body,
+        )
+        .await
+    {
+        Ok(()) => mark_processed(transaction, message.message_id, "sent").await?,
+        Err(SendEmailError::CircuitOpen(_)) => {
+            transaction
+                .rollback()
+                .await
+                .context("Failed to release an outbox message.")?;
+            return Ok(DispatchOutcome::ProviderUnavailable);
+        }
+        Err(SendEmailError::DailyCapReached(e)) => {
+            transaction
+                .rollback()
+                .await
+                .context("Failed to release an outbox message.")?;
+            return Ok(DispatchOutcome::QuotaExhausted {
+                resumes_at: e.resets_at,
+            });
+        }
+        // nothing was sent, the message is released and retried
+        Err(e @ SendEmailError::DailyCountFailed(_)) => return Err(e.into()),
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to send an outbox message."
+            );
+            record_failed_attempt(transaction, message.message_id, message.n_attempts + 1).await?;
+        }
+    }
+
+    Ok(DispatchOutcome::MessageProcessed)
+}
+
+struct PendingMessage {
+    message_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_body: String,
+    text_body: String,
+    n_attempts: i16,
+}
+
+// the row lock is held until the transaction ends, so concurrent dispatchers never pick the same message
+#[tracing::instrument(skip_all)]
+async fn dequeue_message(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, PendingMessage)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let message = sqlx::query_as!(
+        PendingMessage,
+        r#"
+        SELECT message_id, recipient, subject, html_body, text_body, n_attempts
+        FROM email_outbox
+        WHERE status = 'pending' AND next_attempt_at <= now()
+        ORDER BY next_attempt_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    Ok(message.map(|message| (transaction, message)))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn mark_processed(
+    mut transaction: PgTransaction,
+    message_id: Uuid,
+    status: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET status = $2, processed_at = now()
+        WHERE message_id = $1
+        "#,
+        message_id,
+        status
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+// retries back off exponentially, from one minute after the first failure
+#[tracing::instrument(skip(transaction))]
+async fn record_failed_attempt(
+    mut transaction: PgTransaction,
+    message_id: Uuid,
+    n_attempts: i16,
+) -> Result<(), anyhow::Error> {
+    let backoff_minutes = 1 << (n_attempts - 1).clamp(0, 10);
+    let status = if n_attempts >= MAX_ATTEMPTS {
+        "failed"
+    } else {
+        "pending"
+    };
+    let query = sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_attempts = $2,
+            next_attempt_at = now() + make_interval(mins => $3),
+            status = $4,
+            processed_at = CASE WHEN $4 = 'failed' THEN now() END
+        WHERE message_id = $1
+        "#,
+        message_id,
+        n_attempts,
+        backoff_minutes,
+        status
+    );
+    transaction.execute(query).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::configuration::TokenBucketSettings;
+use crate::rate_limiting::{RateLimitDecision, RateLimiter};
+
+// buckets that never refill would ask to retry after centuries
+const MAX_RETRY_AFTER_SECONDS: u64 = 24 * 60 * 60;
+
+#[derive(serde::Deserialize)]
+struct TargetEmail {
+    email: String,
+}
+
+// every request takes a token from the bucket of its client ip and, for subscription forms,
+// from the bucket of the targeted email, so a single address cannot be flooded from many ips
+pub async fn rate_limit(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let limiter = req
+        .app_data::<web::Data<RateLimiter>>()
+        .expect("The rate limiter is not registered as application data.")
+        .clone();
+    let settings = limiter.settings();
+
+    let peer_ip = req.peer_addr().map(|addr| addr.ip());
+    let client_ip = if settings.trust_proxy_headers {
+        proxied_client_ip(req.headers()).or(peer_ip)
+    } else {
+        peer_ip
+    };
+    if let Some(client_ip) = client_ip {
+        check(&limiter, &format!("ip:{client_ip}"), settings.per_ip).await?;
+    }
+
+    if req.content_type() == "application/x-www-form-urlencoded" {
+        let body = req.extract::<web::Bytes>().await?;
+        if let Ok(target) = serde_urlencoded::from_bytes::<TargetEmail>(&body) {
+            let key = format!("email:{}", target.email.trim().to_lowercase());
+            check(&limiter, &key, settings.per_email).await?;
+        }
+        // the body was consumed by the extractor, the handler gets it back
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+        payload.unread_data(body);
+        req.set_payload(payload.into());
+    }
+
+    next.call(req).await
+}
+
+// the limiter fails open: an unavailable backend must not take subscriptions down with it
+async fn check(
+    limiter: &RateLimiter,
+    key: &str,
+    settings: TokenBucketSettings,
+) -> Result<(), actix_web::Error> {
+    match limiter.acquire(key, settings).await {
+        Ok(RateLimitDecision::Allowed) => Ok(()),
+        Ok(RateLimitDecision::Limited { retry_after }) => Err(too_many_requests(retry_after)),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to apply the rate limit.");
+            Ok(())
+        }
+    }
+}
+
+// the client controls the leftmost entries of 'X-Forwarded-For', only the address appended by
+// the trusted proxy, the rightmost one, tells who connected to it
+fn proxied_client_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    let forwarded_for = headers.get_all("X-Forwarded-For").last()?.to_str().ok()?;
+    forwarded_for.rsplit(',').next()?.trim().parse().ok()
+}
+
+fn too_many_requests(retry_after: Duration) -> actix_web::Error {
+    // 'Retry-After' is expressed in whole seconds, rounded up so the client does not retry too early
+    let seconds = retry_after
+        .as_secs()
+        .saturating_add(u64::from(retry_after.subsec_nanos() > 0))
+        .min(MAX_RETRY_AFTER_SECONDS);
+    let response = HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, seconds.to_string()))
+        .finish();
+
+    InternalError::from_response("Too many requests.", response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proxied_client_ip;
+    use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+
+    fn forwarded_for(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(
+                HeaderName::from_static("x-forwarded-for"),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn the_address_appended_by_the_proxy_is_used() {
+        let headers = forwarded_for(&["203.0.113.7, 198.51.100.2"]);
+
+        assert_eq!(
+            proxied_client_ip(&headers),
+            Some("198.51.100.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn the_last_header_is_the_one_of_the_proxy() {
+        let headers = forwarded_for(&["203.0.113.7", "198.51.100.2"]);
+
+        assert_eq!(
+            proxied_client_ip(&headers),
+            Some("198.51.100.2".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn missing_or_invalid_addresses_are_ignored() {
+        assert_eq!(proxied_client_ip(&HeaderMap::new()), None);
+        assert_eq!(
+            proxied_client_ip(&forwarded_for(&["203.0.113.7, unknown"])),
+            None
+        );
+    }
+}
@@ -0,0 +1,238 @@
+mod middleware;
+mod token_bucket;
+
+pub use middleware::*;
+pub use token_bucket::*;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::configuration::{RateLimitBackend, RateLimitSettings, TokenBucketSettings};
+
+// past this many buckets, the in-memory store forgets the ones that refilled completely
+// and, if that is not enough, the least recently used ones down to half of the limit
+const MAX_IN_MEMORY_BUCKETS: usize = 10_000;
+// how often the Postgres store deletes the buckets that refilled completely
+const SWEEP_INTERVAL: chrono::Duration = chrono::Duration::minutes(1);
+
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    store: BucketStore,
+}
+
+// limits do not share the same settings, each bucket is kept with its own
+type InMemoryBuckets = HashMap<String, (TokenBucketSettings, TokenBucket)>;
+
+enum BucketStore {
+    InMemory(Mutex<InMemoryBuckets>),
+    Postgres {
+        pool: PgPool,
+        last_sweep: Mutex<Option<DateTime<Utc>>>,
+    },
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings, pool: PgPool) -> Self {
+        let store = match settings.backend {
+            RateLimitBackend::InMemory => BucketStore::InMemory(Mutex::new(HashMap::new())),
+            RateLimitBackend::Postgres => BucketStore::Postgres {
+                pool,
+                last_sweep: Mutex::new(None),
+            },
+        };
+
+        Self { settings, store }
+    }
+
+    pub fn settings(&self) -> &RateLimitSettings {
+        &self.settings
+    }
+
+    // takes a token from the bucket identified by 'key', creating it full if needed
+    #[tracing::instrument(name = "Acquire a rate limit token", skip(self, settings))]
+    pub async fn acquire(
+        &self,
+        key: &str,
+        settings: TokenBucketSettings,
+    ) -> Result<RateLimitDecision, anyhow::Error> {
+        let now = Utc::now();
+
+        match &self.store {
+            BucketStore::InMemory(buckets) => {
+                let mut buckets = buckets.lock().unwrap();
+                if buckets.len() >= MAX_IN_MEMORY_BUCKETS && !buckets.contains_key(key) {
+                    evict_buckets(&mut buckets, now, MAX_IN_MEMORY_BUCKETS / 2);
+                }
+                let (_, bucket) = buckets
+                    .entry(key.to_string())
+                    .or_insert_with(|| (settings, TokenBucket::full(settings, now)));
+
+                Ok(bucket.take(settings, now))
+            }
+            BucketStore::Postgres { pool, last_sweep } => {
+                let sweep_due = {
+                    let mut last_sweep = last_sweep.lock().unwrap();
+                    let due = last_sweep.is_none_or(|last| now - last >= SWEEP_INTERVAL);
+                    if due {
+                        *last_sweep = Some(now);
+                    }
+                    due
+                };
+                // a failed sweep is retried later, it does not fail the request
+                if sweep_due {
+                    if let Err(e) = sweep_full_buckets(pool).await {
+                        tracing::error!(
+                            error.cause_chain = ?e,
+                            error.message = %e,
+                            "Failed to delete the rate limit buckets that refilled completely."
+                        );
+                    }
+                }
+                acquire_from_postgres(pool, key, settings).await
+            }
+        }
+    }
+}
+
+// a bucket is last updated when a token is taken from it, which tells how recently it was used.
+// going down to 'keep' buckets spreads the cost of the scans over many new keys
+fn evict_buckets(buckets: &mut InMemoryBuckets, now: DateTime<Utc>, keep: usize) {
+    buckets.retain(|_, (settings, bucket)| !bucket.is_full(*settings, now));
+    if buckets.len() <= keep {
+        return;
+    }
+
+    let mut last_used: Vec<_> = buckets
+        .values()
+        .map(|(_, bucket)| bucket.updated_at)
+        .collect();
+    // the most recent of the buckets that go, those used at the same time go with it
+    let newest_evicted = last_used.len() - keep - 1;
+    let (_, cutoff, _) = last_used.select_nth_unstable(newest_evicted);
+    let cutoff = *cutoff;
+    buckets.retain(|_, (_, bucket)| bucket.updated_at > cutoff);
+}
+
+#[tracing::instrument(name = "Delete the rate limit buckets that refilled", skip_all)]
+async fn sweep_full_buckets(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM rate_limit_buckets WHERE full_at <= now()")
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+async fn acquire_from_postgres(
+    pool: &PgPool,
+    key: &str,
+    settings: TokenBucketSettings,
+) -> Result<RateLimitDecision, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    // the row lock serialises concurrent requests for the same key across instances
+    let full = TokenBucket::full(settings, Utc::now());
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO rate_limit_buckets (key, tokens, updated_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (key) DO UPDATE SET key = EXCLUDED.key
+        RETURNING tokens, updated_at
+        "#,
+        key,
+        full.tokens,
+        full.updated_at
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to retrieve a rate limit bucket.")?;
+
+    let mut bucket = TokenBucket {
+        tokens: row.tokens,
+        updated_at: row.updated_at,
+    };
+    let decision = bucket.take(settings, Utc::now());
+
+    sqlx::query!(
+        r#"
+        UPDATE rate_limit_buckets
+        SET tokens = $2, updated_at = $3, full_at = $4
+        WHERE key = $1
+        "#,
+        key,
+        bucket.tokens,
+        bucket.updated_at,
+        bucket.full_at(settings)
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update a rate limit bucket.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a rate limit bucket.")?;
+
+    Ok(decision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evict_buckets, InMemoryBuckets, TokenBucket};
+    use crate::configuration::TokenBucketSettings;
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn buckets_are_evicted_according_to_their_own_settings() {
+        let now = Utc::now();
+        let small = TokenBucketSettings {
+            capacity: 3,
+            refill_per_minute: 60,
+        };
+        let large = TokenBucketSettings {
+            capacity: 20,
+            refill_per_minute: 60,
+        };
+        let mut buckets = InMemoryBuckets::new();
+        // holds more tokens than the small capacity, but is not full
+        let mut bucket = TokenBucket::full(large, now);
+        bucket.take(large, now);
+        buckets.insert("ip".into(), (large, bucket));
+        buckets.insert("email".into(), (small, TokenBucket::full(small, now)));
+
+        evict_buckets(&mut buckets, now, 10);
+        assert!(buckets.contains_key("ip"));
+        assert!(!buckets.contains_key("email"));
+
+        evict_buckets(&mut buckets, now + Duration::seconds(1), 10);
+        assert!(buckets.is_empty());
+    }
+
+    #[test]
+    fn the_least_recently_used_buckets_are_evicted_past_the_limit() {
+        let now = Utc::now();
+        let settings = TokenBucketSettings {
+            capacity: 3,
+            refill_per_minute: 1,
+        };
+        let mut buckets = InMemoryBuckets::new();
+        for second in 0..4 {
+            let used_at = now - Duration::seconds(10 - second);
+            let mut bucket = TokenBucket::full(settings, used_at);
+            bucket.take(settings, used_at);
+            bucket.take(settings, used_at);
+            bucket.take(settings, used_at);
+            buckets.insert(second.to_string(), (settings, bucket));
+        }
+
+        evict_buckets(&mut buckets, now, 2);
+
+        let mut kept: Vec<_> = buckets.keys().cloned().collect();
+        kept.sort();
+        assert_eq!(kept, ["2", "3"]);
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+use crate::configuration::TokenBucketSettings;
+
+#[derive(Debug, PartialEq)]
+pub enum RateLimitDecision {
+    Allowed,
+    Limited { retry_after: Duration },
+}
+
+// buckets start full and refill continuously, each request takes a single token
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TokenBucket {
+    pub fn full(settings: TokenBucketSettings, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: settings.capacity.into(),
+            updated_at: now,
+        }
+    }
+
+    pub fn take(&mut self, settings: TokenBucketSettings, now: DateTime<Utc>) -> RateLimitDecision {
+        self.refill(settings, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return RateLimitDecision::Allowed;
+        }
+
+        let refill_per_second = f64::from(settings.refill_per_minute) / 60.0;
+        let retry_after = if refill_per_second > 0.0 {
+            Duration::from_secs_f64((1.0 - self.tokens) / refill_per_second)
+        } else {
+            Duration::MAX
+        };
+        RateLimitDecision::Limited { retry_after }
+    }
+
+    // a bucket left alone until it is full again can be forgotten
+    pub fn is_full(&self, settings: TokenBucketSettings, now: DateTime<Utc>) -> bool {
+        let mut bucket = *self;
+        bucket.refill(settings, now);
+        bucket.tokens >= f64::from(settings.capacity)
+    }
+
+    // when the bucket will be full again if no token is taken, never if it does not refill
+    pub fn full_at(&self, settings: TokenBucketSettings) -> Option<DateTime<Utc>> {
+        let missing = (f64::from(settings.capacity) - self.tokens).max(0.0);
+        let refill_per_second = f64::from(settings.refill_per_minute) / 60.0;
+        if missing == 0.0 {
+            return Some(self.updated_at);
+        }
+        if refill_per_second == 0.0 {
+            return None;
+        }
+        let time_to_full = Duration::try_from_secs_f64(missing / refill_per_second).ok()?;
+        self.updated_at
+            .checked_add_signed(chrono::Duration::from_std(time_to_full).ok()?)
+    }
+
+    fn refill(&mut self, settings: TokenBucketSettings, now: DateTime<Utc>) {
+        // clocks of different instances may disagree, time never flows backwards for a bucket
+        let elapsed = (now - self.updated_at).to_std().unwrap_or_default();
+        let refilled = elapsed.as_secs_f64() * f64::from(settings.refill_per_minute) / 60.0;
+
+        self.tokens = (self.tokens + refilled).min(settings.capacity.into());
+        self.updated_at = self.updated_at.max(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateLimitDecision, TokenBucket};
+    use crate::configuration::TokenBucketSettings;
+    use chrono::{Duration, Utc};
+
+    const SETTINGS: TokenBucketSettings = TokenBucketSettings {
+        capacity: 3,
+        refill_per_minute: 60,
+    };
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_up_to_its_capacity() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket::full(SETTINGS, now);
+
+        for _ in 0..3 {
+            assert_eq!(bucket.take(SETTINGS, now), RateLimitDecision::Allowed);
+        }
+        assert!(matches!(
+            bucket.take(SETTINGS, now),
+            RateLimitDecision::Limited { .. }
+        ));
+    }
+
+    #[test]
+    fn retry_after_is_the_time_needed_to_refill_a_token() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let decision = bucket.take(SETTINGS, now);
+
+        assert_eq!(
+            decision,
+            RateLimitDecision::Limited {
+                retry_after: std::time::Duration::from_secs(1)
+            }
+        );
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let decision = bucket.take(SETTINGS, now + Duration::seconds(1));
+
+        assert_eq!(decision, RateLimitDecision::Allowed);
+    }
+
+    #[test]
+    fn buckets_never_grow_past_their_capacity() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket::full(SETTINGS, now);
+
+        bucket.take(SETTINGS, now + Duration::hours(1));
+
+        assert_eq!(bucket.tokens, 2.0);
+        assert!(bucket.is_full(SETTINGS, now + Duration::hours(2)));
+    }
+
+    #[test]
+    fn a_bucket_is_full_once_its_missing_tokens_are_refilled() {
+        let now = Utc::now();
+        let bucket = TokenBucket {
+            tokens: 1.0,
+            updated_at: now,
+        };
+
+        assert_eq!(bucket.full_at(SETTINGS), Some(now + Duration::seconds(2)));
+        assert_eq!(
+            TokenBucket::full(SETTINGS, now).full_at(SETTINGS),
+            Some(now)
+        );
+        let never_refilled = TokenBucketSettings {
+            capacity: 3,
+            refill_per_minute: 0,
+        };
+        assert_eq!(bucket.full_at(never_refilled), None);
+    }
+
+    #[test]
+    fn a_clock_going_backwards_does_not_refill_the_bucket() {
+        let now = Utc::now();
+        let mut bucket = TokenBucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        let decision = bucket.take(SETTINGS, now - Duration::minutes(5));
+
+        assert!(matches!(decision, RateLimitDecision::Limited { .. }));
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, EmailKind};
+use crate::issue_delivery_worker::get_issue;
+use crate::merge_fields::MergeFields;
+use crate::routes::admin::IssueError;
+use crate::startup::ApplicationBaseUrl;
+use crate::suppression_list::is_suppressed;
+
+#[tracing::instrument(name = "Preview a newsletter issue", skip(pool, base_url))]
+pub async fn preview_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, IssueError> {
+    let issue = get_issue(&pool, *newsletter_issue_id)
+        .await
+        .context("Failed to retrieve a newsletter issue.")?
+        .ok_or(IssueError::UnknownIssue)?;
+    // there is no recipient to preview for, merge fields fall back to their default
+    let rendered = issue.render(&base_url.0, &MergeFields::default(), None)?;
+
+    Ok(HttpResponse::Ok().json(rendered))
+}
+
+// delivers the issue to the logged-in admin only, the subscriber queue is left untouched
+#[tracing::instrument(
+    name = "Send a test newsletter issue",
+    skip(user_id, pool, email_client, base_url),
+    fields(user_id = %*user_id)
+)]
+pub async fn send_test_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, IssueError> {
+    let issue = get_issue(&pool, *newsletter_issue_id)
+        .await
+        .context("Failed to retrieve a newsletter issue.")?
+        .ok_or(IssueError::UnknownIssue)?;
+    let rendered = issue.render(&base_url.0, &MergeFields::default(), None)?;
+    let recipient = get_user_email(&pool, **user_id).await?;
+    if is_suppressed(&pool, &recipient)
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        return Err(IssueError::ValidationError(
+            "Your address is on the suppression list.".into(),
+        ));
+    }
+
+    email_client
+        .send_email(
+            EmailKind::Test,
+            recipient,
+            &rendered.subject,
+            &rendered.html_body,
+            &rendered.text_body,
+        )
+        .await
+        .context("Failed to send a test newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Get the email of a user", skip(pool))]
+async fn get_user_email(pool: &PgPool, user_id: Uuid) -> Result<SubscriberEmail, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email FROM users WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to retrieve the email of a user.")?;
+
+    SubscriberEmail::parse(row.email).map_err(|e| anyhow::anyhow!(e))
+}
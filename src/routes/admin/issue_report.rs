@@ -0,0 +1,111 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::routes::admin::IssueError;
+use crate::startup::ReadPool;
+
+#[derive(serde::Serialize)]
+struct IssueReport {
+    newsletter_issue_id: Uuid,
+    queued: i64,
+    sent: i64,
+    failed: i64,
+    bounced: i64,
+    opened: i64,
+    clicked: i64,
+    unsubscribed_after_issue: i64,
+    sends_per_minute: Vec<SendsPerMinute>,
+}
+
+#[derive(serde::Serialize)]
+struct SendsPerMinute {
+    minute: DateTime<Utc>,
+    sent: i64,
+}
+
+// bounces are matched to a delivery by the id Postmark gave to the email, soft bounces may
+// resolve on their own and are not counted. unsubscriptions are not tied to a specific email:
+// they are attributed to the last issue a subscriber received before leaving
+#[tracing::instrument(name = "Report on a newsletter issue", skip(pool))]
+pub async fn issue_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, IssueError> {
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT count(*) FROM issue_delivery_queue q
+                WHERE q.newsletter_issue_id = i.newsletter_issue_id) AS "queued!",
+            (SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.status = 'sent') AS "sent!",
+            (SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.status = 'failed') AS "failed!",
+            (SELECT count(*) FROM issue_deliveries d
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND EXISTS (
+                    SELECT 1 FROM email_events e
+                    WHERE e.record_type = 'Bounce'
+                    AND e.event_type = 'HardBounce'
+                    AND e.message_id = d.message_id
+                )) AS "bounced!",
+            (SELECT count(DISTINCT t.delivery_id) FROM tracking_events t
+                JOIN issue_deliveries d ON d.delivery_id = t.delivery_id
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND t.kind = 'open') AS "opened!",
+            (SELECT count(DISTINCT t.delivery_id) FROM tracking_events t
+                JOIN issue_deliveries d ON d.delivery_id = t.delivery_id
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND t.kind = 'click') AS "clicked!",
+            (SELECT count(*) FROM issue_deliveries d
+                JOIN subscriptions s ON s.email = d.subscriber_email
+                WHERE d.newsletter_issue_id = i.newsletter_issue_id
+                AND d.status = 'sent'
+                AND s.unsubscribed_at >= d.attempted_at
+                AND NOT EXISTS (
+                    SELECT 1 FROM issue_deliveries n
+                    WHERE n.subscriber_email = d.subscriber_email
+                    AND n.status = 'sent'
+                    AND n.attempted_at > d.attempted_at
+                    AND n.attempted_at <= s.unsubscribed_at
+                )) AS "unsubscribed_after_issue!"
+        FROM newsletter_issues i
+        WHERE i.newsletter_issue_id = $1
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(&pool.0)
+    .await
+    .context("Failed to compute the delivery counts of a newsletter issue.")?
+    .ok_or(IssueError::UnknownIssue)?;
+
+    let sends_per_minute = sqlx::query_as!(
+        SendsPerMinute,
+        r#"
+        SELECT date_trunc('minute', attempted_at) AS "minute!", count(*) AS "sent!"
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status = 'sent'
+        GROUP BY 1
+        ORDER BY 1
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_all(&pool.0)
+    .await
+    .context("Failed to compute the sends per minute of a newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().json(IssueReport {
+        newsletter_issue_id: *newsletter_issue_id,
+        queued: counts.queued,
+        sent: counts.sent,
+        failed: counts.failed,
+        bounced: counts.bounced,
+        opened: counts.opened,
+        clicked: counts.clicked,
+        unsubscribed_after_issue: counts.unsubscribed_after_issue,
+        sends_per_minute,
+    }))
+}
@@ -0,0 +1,385 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::newsletter_html::{prepare_newsletter_html, NewsletterHtmlError};
+use crate::routes::{
+    error_chain_fmt, insert_newsletter_issue, issue_slug, unexpected_error_status,
+    validate_issue_merge_fields, Content, IssueOptions,
+};
+use crate::startup::{ApplicationBaseUrl, ReadPool};
+
+#[derive(serde::Deserialize)]
+pub struct DraftData {
+    title: String,
+    content: Content,
+    #[serde(flatten)]
+    options: IssueOptions,
+}
+
+#[derive(serde::Serialize)]
+struct DraftCreated {
+    newsletter_issue_id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+struct IssueSummary {
+    newsletter_issue_id: Uuid,
+    title: String,
+    slug: String,
+    is_public: bool,
+    status: String,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct IssueDetails {
+    newsletter_issue_id: Uuid,
+    title: String,
+    content: IssueContent,
+    slug: String,
+    #[serde(flatten)]
+    options: IssueOptions,
+    status: String,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize)]
+struct IssueContent {
+    html: String,
+    text: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum IssueError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no newsletter issue with the given id.")]
+    UnknownIssue,
+    #[error("The newsletter issue has already been published.")]
+    AlreadyPublished,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for IssueError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IssueError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            IssueError::UnknownIssue => StatusCode::NOT_FOUND,
+            IssueError::AlreadyPublished => StatusCode::CONFLICT,
+            IssueError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<NewsletterHtmlError> for IssueError {
+    fn from(e: NewsletterHtmlError) -> Self {
+        match e {
+            NewsletterHtmlError::InvalidStylesheet(_) => Self::ValidationError(e.to_string()),
+            NewsletterHtmlError::InvalidBaseUrl(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+#[tracing::instrument(name = "List newsletter issues", skip(pool))]
+pub async fn list_issues(pool: web::Data<ReadPool>) -> Result<HttpResponse, IssueError> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            slug,
+            is_public,
+            CASE
+                WHEN published_at IS NOT NULL THEN 'published'
+                WHEN scheduled_at IS NOT NULL THEN 'scheduled'
+                ELSE 'draft'
+            END AS "status!",
+            scheduled_at,
+            published_at
+        FROM newsletter_issues
+        ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(&pool.0)
+    .await
+    .context("Failed to retrieve newsletter issues.")?;
+
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+#[tracing::instrument(name = "Get a newsletter issue", skip(pool))]
+pub async fn get_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, IssueError> {
+    let issue = sqlx::query!(
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            slug,
+            is_public,
+            track_opens,
+            track_clicks,
+            CASE
+                WHEN published_at IS NOT NULL THEN 'published'
+                WHEN scheduled_at IS NOT NULL THEN 'scheduled'
+                ELSE 'draft'
+            END AS "status!",
+            scheduled_at,
+            published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to retrieve a newsletter issue.")?
+    .ok_or(IssueError::UnknownIssue)?;
+
+    Ok(HttpResponse::Ok().json(IssueDetails {
+        newsletter_issue_id: issue.newsletter_issue_id,
+        title: issue.title,
+        content: IssueContent {
+            html: issue.html_content,
+            text: issue.text_content,
+        },
+        slug: issue.slug,
+        options: IssueOptions {
+            is_public: issue.is_public,
+            track_opens: issue.track_opens,
+            track_clicks: issue.track_clicks,
+        },
+        status: issue.status,
+        scheduled_at: issue.scheduled_at,
+        published_at: issue.published_at,
+    }))
+}
+
+#[tracing::instrument(
+    name = "Create a newsletter draft",
+    skip(body, pool, base_url),
+    fields(newsletter_issue_id = tracing::field::Empty)
+)]
+pub async fn create_draft(
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, IssueError> {
+    prepare_newsletter_html(&body.content.html, &base_url.0)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let newsletter_issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &body.title,
+        &body.content.text,
+        &body.content.html,
+        &body.options,
+        None,
+        None,
+    )
+    .await
+    .context("Failed to store newsletter draft details.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a newsletter draft.")?;
+    tracing::Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(newsletter_issue_id),
+    );
+
+    Ok(HttpResponse::Created().json(DraftCreated {
+        newsletter_issue_id,
+    }))
+}
+
+// scheduled issues can still be edited, published ones are final.
+// the slug follows the title until the issue is published
+#[tracing::instrument(name = "Update a newsletter draft", skip(body, pool, base_url))]
+pub async fn update_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<DraftData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, IssueError> {
+    prepare_newsletter_html(&body.content.html, &base_url.0)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // the row lock keeps the issue from being scheduled between validation and update
+    let issue = sqlx::query!(
+        r#"
+        SELECT scheduled_at FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        FOR UPDATE
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve a newsletter draft.")?;
+    let Some(issue) = issue else {
+        return Err(unpublished_issue_not_found(&pool, *newsletter_issue_id).await);
+    };
+    // scheduled issues go out without any further step, so they are checked as if published now
+    if issue.scheduled_at.is_some() {
+        validate_issue_merge_fields(&body.title, &body.content.text, &body.content.html)
+            .map_err(IssueError::ValidationError)?;
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            text_content = $3,
+            html_content = $4,
+            slug = $5,
+            is_public = $6,
+            track_opens = $7,
+            track_clicks = $8
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        *newsletter_issue_id,
+        body.title,
+        body.content.text,
+        body.content.html,
+        issue_slug(&body.title, *newsletter_issue_id),
+        body.options.is_public,
+        body.options.track_opens,
+        body.options.track_clicks
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update a newsletter draft.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to update a newsletter draft.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Delete a newsletter draft", skip(pool))]
+pub async fn delete_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, IssueError> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        *newsletter_issue_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete a newsletter draft.")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(unpublished_issue_not_found(&pool, *newsletter_issue_id).await);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Publish a newsletter draft", skip(pool))]
+pub async fn publish_draft(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, IssueError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    // the row lock keeps the content from changing between validation and publication
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        FOR UPDATE
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve a newsletter draft.")?;
+
+    let Some(issue) = issue else {
+        return Err(unpublished_issue_not_found(&pool, *newsletter_issue_id).await);
+    };
+    validate_issue_merge_fields(&issue.title, &issue.text_content, &issue.html_content)
+        .map_err(IssueError::ValidationError)?;
+
+    // a pending schedule is dropped: the issue goes out now
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = now(), scheduled_at = NULL
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        *newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to publish a newsletter draft.")?;
+
+    enqueue_delivery_tasks(&mut transaction, *newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter draft.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// tells apart a missing issue from a published one, after an update matched no unpublished issue
+#[tracing::instrument(
+    name = "Check whether a newsletter issue exists and is unpublished",
+    skip(pool)
+)]
+async fn unpublished_issue_not_found(pool: &PgPool, newsletter_issue_id: Uuid) -> IssueError {
+    let exists = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a newsletter issue.");
+
+    match exists {
+        Ok(Some(_)) => IssueError::AlreadyPublished,
+        Ok(None) => IssueError::UnknownIssue,
+        Err(e) => IssueError::UnexpectedError(e),
+    }
+}
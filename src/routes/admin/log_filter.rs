@@ -0,0 +1,74 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use std::time::Duration;
+
+use crate::routes::{error_chain_fmt, unexpected_error_status};
+use crate::telemetry::{LogFilter, LogFilterError};
+
+#[derive(serde::Deserialize)]
+pub struct LogFilterData {
+    // an 'EnvFilter' directive, e.g. 'info,zero2prod_newsletter=debug'
+    directive: String,
+    // the previous directive is restored after this long, when set
+    revert_after_seconds: Option<u64>,
+}
+
+// a forgotten temporary filter still reverts within a reasonable time
+const MAX_REVERT_AFTER_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(thiserror::Error)]
+pub enum UpdateLogFilterError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UpdateLogFilterError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UpdateLogFilterError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            UpdateLogFilterError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for UpdateLogFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub async fn get_log_filter(log_filter: web::Data<LogFilter>) -> HttpResponse {
+    HttpResponse::Ok().json(log_filter.state())
+}
+
+#[tracing::instrument(name = "Update the log filter", skip(body, log_filter))]
+pub async fn update_log_filter(
+    body: web::Json<LogFilterData>,
+    log_filter: web::Data<LogFilter>,
+) -> Result<HttpResponse, UpdateLogFilterError> {
+    let revert_after = match body.revert_after_seconds {
+        Some(0) => {
+            return Err(UpdateLogFilterError::ValidationError(
+                "'revert_after_seconds' must be greater than zero.".into(),
+            ))
+        }
+        Some(seconds) if seconds > MAX_REVERT_AFTER_SECONDS => {
+            return Err(UpdateLogFilterError::ValidationError(format!(
+                "'revert_after_seconds' must be at most {MAX_REVERT_AFTER_SECONDS}."
+            )))
+        }
+        seconds => seconds.map(Duration::from_secs),
+    };
+
+    log_filter
+        .set(&body.directive, revert_after)
+        .map_err(|e| match e {
+            LogFilterError::InvalidDirective(_) | LogFilterError::RevertTooLate => {
+                UpdateLogFilterError::ValidationError(e.to_string())
+            }
+            LogFilterError::ReloadFailed(_) => UpdateLogFilterError::UnexpectedError(e.into()),
+        })?;
+
+    Ok(HttpResponse::Ok().json(log_filter.state()))
+}
@@ -0,0 +1,11 @@
+mod issue_preview;
+mod issue_report;
+mod issues;
+mod log_filter;
+mod suppressions;
+
+pub use issue_preview::*;
+pub use issue_report::*;
+pub use issues::*;
+pub use log_filter::*;
+pub use suppressions::*;
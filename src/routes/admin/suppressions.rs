@@ -0,0 +1,135 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::routes::{error_chain_fmt, unexpected_error_status};
+use crate::startup::ReadPool;
+use crate::suppression_list::{insert_suppression, SuppressionKind, SuppressionSource};
+
+#[derive(serde::Deserialize)]
+pub struct SuppressionData {
+    kind: SuppressionKind,
+    value: String,
+    reason: String,
+    source: SuppressionSource,
+}
+
+#[derive(serde::Serialize)]
+struct SuppressionCreated {
+    entry_id: Uuid,
+}
+
+#[derive(serde::Serialize)]
+struct SuppressionEntry {
+    entry_id: Uuid,
+    kind: String,
+    value: String,
+    reason: String,
+    source: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SuppressionError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The entry is already on the suppression list.")]
+    AlreadySuppressed,
+    #[error("There is no suppression list entry with the given id.")]
+    UnknownEntry,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for SuppressionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SuppressionError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SuppressionError::AlreadySuppressed => StatusCode::CONFLICT,
+            SuppressionError::UnknownEntry => StatusCode::NOT_FOUND,
+            SuppressionError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for SuppressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "List suppression list entries", skip(pool))]
+pub async fn list_suppressions(
+    pool: web::Data<ReadPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let entries = sqlx::query_as!(
+        SuppressionEntry,
+        r#"
+        SELECT entry_id, kind, value, reason, source, created_at
+        FROM suppression_list
+        ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(&pool.0)
+    .await
+    .context("Failed to retrieve suppression list entries.")?;
+
+    Ok(HttpResponse::Ok().json(entries))
+}
+
+#[tracing::instrument(name = "Add a suppression list entry", skip(body, pool))]
+pub async fn add_suppression(
+    body: web::Json<SuppressionData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let value = body
+        .kind
+        .parse_value(&body.value)
+        .map_err(SuppressionError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let entry_id = insert_suppression(
+        &mut transaction,
+        body.kind,
+        &value,
+        &body.reason,
+        body.source,
+    )
+    .await
+    .context("Failed to store a suppression list entry.")?
+    .ok_or(SuppressionError::AlreadySuppressed)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a suppression list entry.")?;
+
+    Ok(HttpResponse::Created().json(SuppressionCreated { entry_id }))
+}
+
+#[tracing::instrument(name = "Remove a suppression list entry", skip(pool))]
+pub async fn delete_suppression(
+    entry_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, SuppressionError> {
+    let deleted = sqlx::query!(
+        r#"
+        DELETE FROM suppression_list WHERE entry_id = $1
+        "#,
+        *entry_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete a suppression list entry.")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(SuppressionError::UnknownEntry);
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,257 @@
+use actix_web::{http::header::ContentType, http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::merge_fields::{substitute_merge_fields, ContentFormat, MergeFields};
+use crate::newsletter_html::prepare_newsletter_html;
+use crate::routes::{error_chain_fmt, unexpected_error_status};
+use crate::startup::{ApplicationBaseUrl, ReadPool};
+
+// the feed only carries the latest issues
+const FEED_ENTRIES: i64 = 20;
+// web views kept in memory, the oldest issues are dropped first
+const MAX_CACHED_WEB_VIEWS: usize = 100;
+
+struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    slug: String,
+    html_content: String,
+    published_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ArchiveError {
+    #[error("There is no public newsletter issue with the given slug.")]
+    UnknownIssue,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ArchiveError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ArchiveError::UnknownIssue => StatusCode::NOT_FOUND,
+            ArchiveError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// published issues never change, so their web view is prepared once and reused
+#[derive(Default)]
+pub struct WebViewCache(Mutex<HashMap<Uuid, (DateTime<Utc>, String)>>);
+
+impl WebViewCache {
+    fn get_or_prepare(
+        &self,
+        issue: &ArchivedIssue,
+        base_url: &str,
+    ) -> Result<String, anyhow::Error> {
+        if let Some((_, html)) = self.0.lock().unwrap().get(&issue.newsletter_issue_id) {
+            return Ok(html.clone());
+        }
+        // prepared outside of the lock, two requests may both prepare the same issue
+        let html = web_view_html(issue, base_url)?;
+
+        let mut web_views = self.0.lock().unwrap();
+        if web_views.len() >= MAX_CACHED_WEB_VIEWS {
+            let oldest = web_views
+                .iter()
+                .min_by_key(|(_, (published_at, _))| *published_at)
+                .map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                web_views.remove(&oldest);
+            }
+        }
+        web_views.insert(
+            issue.newsletter_issue_id,
+            (issue.published_at, html.clone()),
+        );
+
+        Ok(html)
+    }
+}
+
+#[tracing::instrument(name = "List archived newsletter issues", skip(pool))]
+pub async fn archive(pool: web::Data<ReadPool>) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_archived_issues(&pool.0, None).await?;
+
+    let mut entries = String::new();
+    for issue in &issues {
+        writeln!(
+            entries,
+            r#"<li><a href="/archive/{}">{}</a> - {}</li>"#,
+            encode_minimal(&issue.slug),
+            encode_minimal(&issue.title),
+            issue.published_at.format("%B %-d, %Y"),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <link rel="alternate" type="application/atom+xml" href="/archive/feed.xml">
+    <title>Newsletter archive</title>
+</head>
+<body>
+    <h1>Newsletter archive</h1>
+    <ul>
+{entries}    </ul>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "View an archived newsletter issue",
+    skip(pool, base_url, web_views)
+)]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<ReadPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    web_views: web::Data<WebViewCache>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issue = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT newsletter_issue_id, title, slug, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE slug = $1 AND is_public AND published_at IS NOT NULL
+        "#,
+        slug.as_str()
+    )
+    .fetch_optional(&pool.0)
+    .await
+    .context("Failed to retrieve an archived newsletter issue.")?
+    .ok_or(ArchiveError::UnknownIssue)?;
+
+    let html = web_views.get_or_prepare(&issue, &base_url.0)?;
+    let title = encode_minimal(&issue.title);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>Published on {published_at}</p>
+    {content}
+    <p><a href="/archive">Back to the archive</a></p>
+</body>
+</html>"#,
+            published_at = issue.published_at.format("%B %-d, %Y"),
+            content = html,
+        )))
+}
+
+#[tracing::instrument(name = "Build the archive Atom feed", skip(pool, base_url, web_views))]
+pub async fn archive_feed(
+    pool: web::Data<ReadPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    web_views: web::Data<WebViewCache>,
+) -> Result<HttpResponse, ArchiveError> {
+    let issues = get_archived_issues(&pool.0, Some(FEED_ENTRIES)).await?;
+    let base_url = &base_url.0;
+
+    // the feed is as recent as its latest entry
+    let updated = issues
+        .first()
+        .map(|issue| issue.published_at)
+        .unwrap_or_else(Utc::now);
+
+    let mut entries = String::new();
+    for issue in &issues {
+        let link = format!("{base_url}/archive/{}", issue.slug);
+        let html = web_views.get_or_prepare(issue, base_url)?;
+        writeln!(
+            entries,
+            r#"  <entry>
+    <title>{title}</title>
+    <id>{link}</id>
+    <link href="{link}"/>
+    <updated>{published_at}</updated>
+    <content type="html">{content}</content>
+  </entry>"#,
+            title = encode_minimal(&issue.title),
+            link = encode_minimal(&link),
+            published_at = issue.published_at.to_rfc3339(),
+            content = encode_minimal(&html),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Newsletter archive</title>
+  <id>{base_url}/archive</id>
+  <link href="{base_url}/archive"/>
+  <link rel="self" href="{base_url}/archive/feed.xml"/>
+  <updated>{updated}</updated>
+{entries}</feed>"#,
+            base_url = encode_minimal(base_url),
+            updated = updated.to_rfc3339(),
+        )))
+}
+
+// the archive is not addressed to anyone, merge fields fall back to their default
+fn web_view_html(issue: &ArchivedIssue, base_url: &str) -> Result<String, anyhow::Error> {
+    let html_content = substitute_merge_fields(
+        &issue.html_content,
+        &MergeFields::default(),
+        ContentFormat::Html,
+    );
+    let html = prepare_newsletter_html(&html_content, base_url)
+        .context("Failed to prepare the content of an archived newsletter issue.")?;
+
+    Ok(html.html)
+}
+
+// the latest issues first, all of them without a limit
+#[tracing::instrument(name = "Get archived newsletter issues", skip(pool))]
+async fn get_archived_issues(
+    pool: &PgPool,
+    limit: Option<i64>,
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT newsletter_issue_id, title, slug, html_content, published_at AS "published_at!"
+        FROM newsletter_issues
+        WHERE is_public AND published_at IS NOT NULL
+        ORDER BY published_at DESC
+        LIMIT $1
+        "#,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve archived newsletter issues.")?;
+
+    Ok(issues)
+}
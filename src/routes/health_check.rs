@@ -1,5 +1,225 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
-pub async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().finish()
+use crate::circuit_breaker::CircuitState;
+use crate::email_client::EmailClient;
+use crate::migrations::pending_migrations;
+use crate::startup::ReadPool;
+
+#[derive(serde::Serialize)]
+struct Health {
+    email_provider: EmailProviderHealth,
+}
+
+#[derive(serde::Serialize)]
+struct EmailProviderHealth {
+    circuit_breaker: CircuitState,
+}
+
+// a liveness probe: the application is alive even when its dependencies are not, so this
+// does no I/O and always returns a 200. '/health/ready' checks the dependencies
+pub async fn health_check(email_client: web::Data<EmailClient>) -> HttpResponse {
+    HttpResponse::Ok().json(Health {
+        email_provider: EmailProviderHealth {
+            circuit_breaker: email_client.circuit_state(),
+        },
+    })
+}
+
+// a dependency check gives up after this long, so a hanging dependency cannot hang the probe
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    status: ReadinessStatus,
+    checks: ReadinessChecks,
+}
+
+#[derive(serde::Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ReadinessStatus {
+    Ready,
+    NotReady,
+    // the instance is stopping, load balancers should send its traffic elsewhere
+    ShuttingDown,
+}
+
+#[derive(serde::Serialize)]
+struct ReadinessChecks {
+    database: DependencyCheck,
+    // the primary itself when there is no read replica
+    read_pool: DependencyCheck,
+    migrations: MigrationsCheck,
+    email_provider: EmailProviderCheck,
+    queue_backlog: QueueBacklogCheck,
+}
+
+#[derive(serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(serde::Serialize)]
+struct DependencyCheck {
+    status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct MigrationsCheck {
+    status: CheckStatus,
+    pending: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct EmailProviderCheck {
+    status: CheckStatus,
+    circuit_breaker: CircuitState,
+}
+
+#[derive(serde::Serialize)]
+struct QueueBacklogCheck {
+    status: CheckStatus,
+    issue_deliveries: Option<i64>,
+    outbox_messages: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// only the database and its schema are critical: emails wait in the outbox and the delivery
+// queue while the provider is down, so the instance can still take traffic. the read replica
+// is shared by every instance and signups do not need it, so it is not critical either
+#[tracing::instrument(name = "Checking readiness", skip_all)]
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    read_pool: web::Data<ReadPool>,
+    email_client: web::Data<EmailClient>,
+    shutdown: web::Data<CancellationToken>,
+) -> HttpResponse {
+    let (database, read_pool, migrations, email_provider, queue_backlog) = tokio::join!(
+        check_database(&pool),
+        check_database(&read_pool.0),
+        check_migrations(&pool),
+        async { check_email_provider(&email_client) },
+        check_queue_backlog(&pool),
+    );
+    let status = if shutdown.is_cancelled() {
+        ReadinessStatus::ShuttingDown
+    } else if database.status == CheckStatus::Up && migrations.status == CheckStatus::Up {
+        ReadinessStatus::Ready
+    } else {
+        ReadinessStatus::NotReady
+    };
+
+    let readiness = Readiness {
+        status,
+        checks: ReadinessChecks {
+            database,
+            read_pool,
+            migrations,
+            email_provider,
+            queue_backlog,
+        },
+    };
+    if readiness.status == ReadinessStatus::Ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn with_timeout<T, E: std::fmt::Display>(
+    check: impl Future<Output = Result<T, E>>,
+) -> Result<T, String> {
+    match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(outcome) => outcome.map_err(|e| e.to_string()),
+        Err(_) => Err("The check timed out.".into()),
+    }
+}
+
+async fn check_database(pool: &PgPool) -> DependencyCheck {
+    match with_timeout(sqlx::query("SELECT 1").execute(pool)).await {
+        Ok(_) => DependencyCheck {
+            status: CheckStatus::Up,
+            error: None,
+        },
+        Err(e) => DependencyCheck {
+            status: CheckStatus::Down,
+            error: Some(e),
+        },
+    }
+}
+
+async fn check_migrations(pool: &PgPool) -> MigrationsCheck {
+    match with_timeout(pending_migrations(pool)).await {
+        Ok(pending) => MigrationsCheck {
+            status: if pending.is_empty() {
+                CheckStatus::Up
+            } else {
+                CheckStatus::Down
+            },
+            pending,
+            error: None,
+        },
+        Err(e) => MigrationsCheck {
+            status: CheckStatus::Down,
+            pending: Vec::new(),
+            error: Some(e),
+        },
+    }
+}
+
+// probes run every few seconds on every instance, they must not turn into requests to the
+// provider: the circuit breaker already knows how the emails we send are faring
+fn check_email_provider(email_client: &EmailClient) -> EmailProviderCheck {
+    let circuit_breaker = email_client.circuit_state();
+    let status = if circuit_breaker == CircuitState::Closed {
+        CheckStatus::Up
+    } else {
+        CheckStatus::Down
+    };
+
+    EmailProviderCheck {
+        status,
+        circuit_breaker,
+    }
+}
+
+async fn check_queue_backlog(pool: &PgPool) -> QueueBacklogCheck {
+    let backlog = with_timeout(async {
+        let issue_deliveries =
+            sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+                .fetch_one(pool)
+                .await?;
+        let outbox_messages = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM email_outbox WHERE status = 'pending'"#
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok::<_, sqlx::Error>((issue_deliveries, outbox_messages))
+    })
+    .await;
+
+    match backlog {
+        Ok((issue_deliveries, outbox_messages)) => QueueBacklogCheck {
+            status: CheckStatus::Up,
+            issue_deliveries: Some(issue_deliveries),
+            outbox_messages: Some(outbox_messages),
+            error: None,
+        },
+        Err(e) => QueueBacklogCheck {
+            status: CheckStatus::Down,
+            issue_deliveries: None,
+            outbox_messages: None,
+            error: Some(e),
+        },
+    }
 }
@@ -0,0 +1,16 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::email_client::EmailClient;
+use crate::metrics::Metrics;
+
+// scraped by Prometheus, in its text exposition format
+pub async fn export_metrics(
+    metrics: web::Data<Metrics>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics.render(&pool, &email_client).await)
+}
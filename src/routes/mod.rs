@@ -1,9 +1,25 @@
+mod admin;
+mod archive;
 mod health_check;
+mod metrics;
 mod newsletters;
+mod newsletters_schedule;
 mod subscriptions;
+mod subscriptions_challenge;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod tracking;
+mod webhooks;
 
+pub use admin::*;
+pub use archive::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use newsletters_schedule::*;
 pub use subscriptions::*;
+pub use subscriptions_challenge::*;
 pub use subscriptions_confirm::*;
+pub use subscriptions_unsubscribe::*;
+pub use tracking::*;
+pub use webhooks::*;
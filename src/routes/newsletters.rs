@@ -1,5 +1,247 @@
-use actix_web::HttpResponse;
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
 
-pub async fn publish_newsletter() -> HttpResponse {
-    HttpResponse::Ok().finish()
+use crate::issue_delivery_worker::enqueue_delivery_tasks;
+use crate::merge_fields::validate_merge_fields;
+use crate::newsletter_html::{prepare_newsletter_html, NewsletterHtmlError};
+use crate::routes::{error_chain_fmt, unexpected_error_status};
+use crate::startup::ApplicationBaseUrl;
+
+#[derive(serde::Deserialize)]
+pub struct BodyData {
+    title: String,
+    content: Content,
+    // when missing, the issue is published right away
+    scheduled_at: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    options: IssueOptions,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct IssueOptions {
+    // public issues are listed in the archive once published
+    #[serde(default)]
+    pub is_public: bool,
+    // tracking only happens if it is also enabled in the tracking settings
+    #[serde(default)]
+    pub track_opens: bool,
+    #[serde(default)]
+    pub track_clicks: bool,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Content {
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(serde::Serialize)]
+struct PublishResponse {
+    newsletter_issue_id: Uuid,
+}
+
+#[derive(thiserror::Error)]
+pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PublishError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl From<NewsletterHtmlError> for PublishError {
+    fn from(e: NewsletterHtmlError) -> Self {
+        match e {
+            NewsletterHtmlError::InvalidStylesheet(_) => Self::ValidationError(e.to_string()),
+            NewsletterHtmlError::InvalidBaseUrl(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, pool, base_url),
+    fields(newsletter_issue_id = tracing::field::Empty)
+)]
+pub async fn publish_newsletter(
+    body: web::Json<BodyData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, PublishError> {
+    let BodyData {
+        title,
+        content,
+        scheduled_at,
+        options,
+    } = body.0;
+
+    // reject content that could not be delivered before anything is stored
+    prepare_newsletter_html(&content.html, &base_url.0)?;
+    validate_issue_merge_fields(&title, &content.text, &content.html)
+        .map_err(PublishError::ValidationError)?;
+    if let Some(scheduled_at) = scheduled_at {
+        validate_schedule(scheduled_at).map_err(PublishError::ValidationError)?;
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &title,
+        &content.text,
+        &content.html,
+        &options,
+        scheduled_at,
+        // scheduled issues are published by the scheduler once they are due
+        scheduled_at.is_none().then(Utc::now),
+    )
+    .await
+    .context("Failed to store newsletter issue details.")?;
+    tracing::Span::current().record("newsletter_issue_id", tracing::field::display(issue_id));
+
+    if scheduled_at.is_none() {
+        enqueue_delivery_tasks(&mut transaction, issue_id)
+            .await
+            .context("Failed to enqueue delivery tasks.")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().json(PublishResponse {
+        newsletter_issue_id: issue_id,
+    }))
+}
+
+pub fn validate_schedule(scheduled_at: DateTime<Utc>) -> Result<(), String> {
+    if scheduled_at <= Utc::now() {
+        Err(format!(
+            "{scheduled_at} is not a valid schedule, it is in the past."
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// placeholders are resolved at send time, unknown ones must be caught before the issue goes out
+pub fn validate_issue_merge_fields(title: &str, text: &str, html: &str) -> Result<(), String> {
+    validate_merge_fields(title)?;
+    validate_merge_fields(text)?;
+    validate_merge_fields(html)
+}
+
+// an issue that is neither scheduled nor published is a draft
+#[tracing::instrument(skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    title: &str,
+    text_content: &str,
+    html_content: &str,
+    options: &IssueOptions,
+    scheduled_at: Option<DateTime<Utc>>,
+    published_at: Option<DateTime<Utc>>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            slug,
+            is_public,
+            track_opens,
+            track_clicks,
+            scheduled_at,
+            published_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        newsletter_issue_id,
+        title,
+        text_content,
+        html_content,
+        issue_slug(title, newsletter_issue_id),
+        options.is_public,
+        options.track_opens,
+        options.track_clicks,
+        scheduled_at,
+        published_at
+    );
+    transaction.execute(query).await?;
+
+    Ok(newsletter_issue_id)
+}
+
+// builds the archive path segment of an issue: the title keeps it readable,
+// the id prefix keeps it unique across issues sharing a title
+pub fn issue_slug(title: &str, newsletter_issue_id: Uuid) -> String {
+    let words: Vec<String> = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect();
+    let mut slug = words.join("-");
+    slug.truncate(60);
+    let slug = slug.trim_end_matches('-');
+
+    let id = newsletter_issue_id.simple().to_string();
+    if slug.is_empty() {
+        id[..8].to_string()
+    } else {
+        format!("{slug}-{}", &id[..8])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::issue_slug;
+    use uuid::Uuid;
+
+    #[test]
+    fn the_slug_is_built_from_the_title_and_the_id() {
+        let id = Uuid::parse_str("1a2b3c4d-0000-0000-0000-000000000000").unwrap();
+
+        assert_eq!(
+            issue_slug("Hello, World! Issue #42", id),
+            "hello-world-issue-42-1a2b3c4d"
+        );
+    }
+
+    #[test]
+    fn titles_without_ascii_alphanumerics_fall_back_to_the_id() {
+        let id = Uuid::parse_str("1a2b3c4d-0000-0000-0000-000000000000").unwrap();
+
+        assert_eq!(issue_slug("¿¡ !?", id), "1a2b3c4d");
+    }
+
+    #[test]
+    fn long_titles_are_truncated() {
+        let id = Uuid::new_v4();
+
+        assert!(issue_slug(&"word ".repeat(50), id).len() <= 69);
+    }
 }
@@ -0,0 +1,145 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::routes::{
+    error_chain_fmt, unexpected_error_status, validate_issue_merge_fields, validate_schedule,
+};
+
+#[derive(serde::Deserialize)]
+pub struct ScheduleData {
+    scheduled_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum ScheduleError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("There is no newsletter issue with the given id.")]
+    UnknownIssue,
+    #[error("The newsletter issue has already been published.")]
+    AlreadyPublished,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for ScheduleError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ScheduleError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ScheduleError::UnknownIssue => StatusCode::NOT_FOUND,
+            ScheduleError::AlreadyPublished => StatusCode::CONFLICT,
+            ScheduleError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(name = "Schedule a newsletter issue", skip(body, pool))]
+pub async fn schedule_newsletter(
+    newsletter_issue_id: web::Path<Uuid>,
+    body: web::Json<ScheduleData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ScheduleError> {
+    validate_schedule(body.scheduled_at).map_err(ScheduleError::ValidationError)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // the scheduler publishes the issue as it is, so its content is checked now.
+    // the row lock keeps the content from changing between validation and scheduling
+    let issue = sqlx::query!(
+        r#"
+        SELECT title, text_content, html_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        FOR UPDATE
+        "#,
+        *newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to retrieve a newsletter issue.")?;
+    if let Some(issue) = issue {
+        validate_issue_merge_fields(&issue.title, &issue.text_content, &issue.html_content)
+            .map_err(ScheduleError::ValidationError)?;
+    }
+    set_schedule(
+        &mut transaction,
+        *newsletter_issue_id,
+        Some(body.scheduled_at),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the SQL transaction to schedule a newsletter issue.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool))]
+pub async fn cancel_newsletter_schedule(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ScheduleError> {
+    let mut connection = pool
+        .acquire()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    set_schedule(&mut connection, *newsletter_issue_id, None).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// published issues are left untouched: if the scheduler is enqueueing the issue right now,
+// the update waits for its row lock and then finds 'published_at' already set
+#[tracing::instrument(name = "Update the schedule of a newsletter issue", skip(connection))]
+async fn set_schedule(
+    connection: &mut PgConnection,
+    newsletter_issue_id: Uuid,
+    scheduled_at: Option<DateTime<Utc>>,
+) -> Result<(), ScheduleError> {
+    let updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_at = $2
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        newsletter_issue_id,
+        scheduled_at
+    )
+    .execute(&mut *connection)
+    .await
+    .context("Failed to update the schedule of a newsletter issue.")?
+    .rows_affected();
+
+    if updated > 0 {
+        return Ok(());
+    }
+
+    let exists = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *connection)
+    .await
+    .context("Failed to look up a newsletter issue.")?
+    .is_some();
+
+    if exists {
+        Err(ScheduleError::AlreadyPublished)
+    } else {
+        Err(ScheduleError::UnknownIssue)
+    }
+}
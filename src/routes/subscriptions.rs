@@ -7,31 +7,79 @@ use rand::Rng;
 use sqlx::{Executor, PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::bot_protection::{verify_challenge, VerifiedChallenge};
+use crate::configuration::BotProtectionSettings;
+use crate::domain::EmailPolicy;
 use crate::domain::NewSubscriber;
 use crate::domain::SubscriberEmail;
 use crate::domain::SubscriberName;
-use crate::email_client::EmailClient;
+use crate::email_domain_check::EmailDomainChecker;
+use crate::metrics::{Metrics, SubscriptionStage};
+use crate::outbox_dispatcher::{enqueue_email, OutboxMessage};
 use crate::startup::ApplicationBaseUrl;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    // honeypot: hidden from humans by the form, only bots fill it in
+    #[serde(default)]
+    website: String,
+    challenge: Option<String>,
+    solution: Option<String>,
 }
 
-impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+enum BotCheck {
+    Automated,
+    // the challenge, when one is required, must still be recorded as used
+    Passed(Option<VerifiedChallenge>),
+}
+
+impl FormData {
+    // bots get the same response as everybody else, so they cannot tell they were caught
+    fn check_bots(&self, bot_protection: &BotProtectionSettings) -> BotCheck {
+        if !self.website.is_empty() {
+            tracing::info!("Discarding a signup that filled in the honeypot field.");
+            return BotCheck::Automated;
+        }
+        if !bot_protection.require_challenge {
+            return BotCheck::Passed(None);
+        }
 
-    fn try_from(value: FormData) -> Result<Self, Self::Error> {
-        let name = SubscriberName::parse(value.name)?;
-        let email = SubscriberEmail::parse(value.email)?;
-        Ok(Self { email, name })
+        match verify_challenge(
+            bot_protection,
+            self.challenge.as_deref(),
+            self.solution.as_deref(),
+            Utc::now(),
+        ) {
+            Ok(challenge) => BotCheck::Passed(Some(challenge)),
+            Err(e) => {
+                tracing::info!(reason = ?e, "Discarding a signup that failed its challenge.");
+                BotCheck::Automated
+            }
+        }
+    }
+}
+
+impl FormData {
+    fn parse(self, email_policy: &EmailPolicy) -> Result<NewSubscriber, String> {
+        let name = SubscriberName::parse(self.name)?;
+        let email = SubscriberEmail::parse_with_policy(self.email, email_policy)?;
+        Ok(NewSubscriber { email, name })
     }
 }
 
 #[tracing::instrument(
     name = "Adding a new subscriber.",
-    skip(form, pool, email_client, base_url),
+    skip(
+        form,
+        pool,
+        base_url,
+        bot_protection,
+        email_policy,
+        email_domain_checker,
+        metrics
+    ),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
@@ -40,16 +88,41 @@ impl TryFrom<FormData> for NewSubscriber {
 pub async fn subscribe(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    // get email client from the app context
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    bot_protection: web::Data<BotProtectionSettings>,
+    email_policy: web::Data<EmailPolicy>,
+    email_domain_checker: web::Data<EmailDomainChecker>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let BotCheck::Passed(challenge) = form.check_bots(&bot_protection) else {
+        return Ok(HttpResponse::Ok().finish());
+    };
+
+    let new_subscriber = form
+        .0
+        .parse(&email_policy)
+        .map_err(SubscribeError::ValidationError)?;
+    email_domain_checker
+        .check(&new_subscriber.email)
+        .await
+        .map_err(SubscribeError::ValidationError)?;
+
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool.")?;
 
+    // recorded in the transaction of the signup, so two concurrent signups cannot share it
+    if let Some(challenge) = &challenge {
+        let first_use = record_challenge_use(&mut transaction, challenge)
+            .await
+            .context("Failed to record the use of a challenge.")?;
+        if !first_use {
+            tracing::info!("Discarding a signup that reused its challenge.");
+            return Ok(HttpResponse::Ok().finish());
+        }
+    }
+
     let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
         .await
         .context("Failed to insert new subscriber in the database.")?;
@@ -58,25 +131,25 @@ pub async fn subscribe(
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         .context("Failed to store the confirmation token for a new subscriber.")?;
+    enqueue_confirmation_email(
+        &mut transaction,
+        &new_subscriber,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to enqueue a confirmation email.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
-
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
+    metrics.record_subscription(SubscriptionStage::Created);
 
     Ok(HttpResponse::Ok().finish())
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -96,7 +169,7 @@ impl ResponseError for SubscribeError {
     fn status_code(&self) -> StatusCode {
         match self {
             SubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
-            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            SubscribeError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
         }
     }
 }
@@ -113,16 +186,40 @@ impl From<String> for SubscribeError {
     }
 }
 
+// returns 'false' if the challenge was already used. expired challenges are rejected before
+// getting here, so their nonces can be forgotten
+#[tracing::instrument(name = "Record the use of a challenge", skip_all)]
+async fn record_challenge_use(
+    transaction: &mut Transaction<'_, Postgres>,
+    challenge: &VerifiedChallenge,
+) -> Result<bool, sqlx::Error> {
+    let query = sqlx::query!("DELETE FROM used_challenges WHERE expires_at < now()");
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO used_challenges (nonce, expires_at)
+        VALUES ($1, $2)
+        ON CONFLICT (nonce) DO NOTHING
+        "#,
+        challenge.nonce,
+        challenge.expires_at
+    );
+
+    Ok(transaction.execute(query).await?.rows_affected() > 0)
+}
+
+// the email is written to the outbox, so it goes out once the subscriber is committed,
+// however long the email provider takes to answer
 #[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber)
+    name = "Enqueue a confirmation email for a new subscriber",
+    skip(transaction, new_subscriber)
 )]
-pub async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+pub async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), sqlx::Error> {
     let confirmation_link =
         format!("{base_url}/subscriptions/confirm?subscription_token={subscription_token}");
 
@@ -135,9 +232,18 @@ pub async fn send_confirmation_email(
         "Welcome to our newsletter!\nVisit {confirmation_link} to confirm your subscription."
     );
 
-    email_client
-        .send_email(new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    enqueue_email(
+        transaction,
+        OutboxMessage {
+            recipient: &new_subscriber.email,
+            subject: "Welcome!",
+            html_body: &html_body,
+            text_body: &plain_body,
+        },
+    )
+    .await?;
+
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -151,13 +257,14 @@ pub async fn insert_subscriber(
     let subscriber_id = Uuid::new_v4();
     let query = sqlx::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-        VALUES($1, $2, $3, $4, 'pending_confirmation')
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status, unsubscribe_token)
+        VALUES($1, $2, $3, $4, 'pending_confirmation', $5)
         "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        generate_subscription_token()
     );
 
     transaction.execute(query).await?;
@@ -212,7 +319,26 @@ impl std::fmt::Debug for StoreTokenError {
     }
 }
 
-fn error_chain_fmt(
+// a database too slow to hand out a connection, or to answer within the statement timeout,
+// is reported as unavailable: the request can be retried, rather than having failed
+pub fn unexpected_error_status(e: &(dyn std::error::Error + 'static)) -> StatusCode {
+    let database_unavailable = std::iter::successors(Some(e), |e| e.source()).any(|cause| {
+        match cause.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::PoolTimedOut) => true,
+            // 'query_canceled', raised when 'statement_timeout' is exceeded
+            Some(sqlx::Error::Database(e)) => e.code().as_deref() == Some("57014"),
+            _ => false,
+        }
+    });
+
+    if database_unavailable {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
 ) -> std::fmt::Result {
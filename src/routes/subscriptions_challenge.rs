@@ -0,0 +1,24 @@
+use actix_web::{http::header, web, HttpResponse};
+use chrono::Utc;
+
+use crate::bot_protection::issue_challenge;
+use crate::configuration::BotProtectionSettings;
+
+#[derive(serde::Serialize)]
+struct Challenge {
+    challenge: String,
+    difficulty: u32,
+}
+
+// the signup form fetches a challenge when it is displayed and submits it, solved, with the form
+#[tracing::instrument(name = "Issue a signup challenge", skip(bot_protection))]
+pub async fn subscription_challenge(
+    bot_protection: web::Data<BotProtectionSettings>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(Challenge {
+            challenge: issue_challenge(&bot_protection, Utc::now()),
+            difficulty: bot_protection.proof_of_work_difficulty,
+        })
+}
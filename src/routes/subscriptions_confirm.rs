@@ -5,23 +5,35 @@ use actix_web::{
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::metrics::{Metrics, SubscriptionStage};
+use crate::routes::unexpected_error_status;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber.", skip(parameters, pool))]
-pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
+#[tracing::instrument(
+    name = "Confirm a pending subscriber.",
+    skip(parameters, pool, metrics)
+)]
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
+) -> HttpResponse {
     let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
         Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+        Err(e) => return HttpResponse::build(unexpected_error_status(&e)).finish(),
     };
 
     match id {
         None => HttpResponse::Unauthorized().finish(),
         Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
+            match confirm_subscriber(&pool, subscriber_id).await {
+                Ok(true) => metrics.record_subscription(SubscriptionStage::Confirmed),
+                Ok(false) => {}
+                Err(e) => return HttpResponse::build(unexpected_error_status(&e)).finish(),
             }
 
             HttpResponse::Ok().finish()
@@ -29,11 +41,14 @@ pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>
     }
 }
 
+// an old confirmation link must not bring back a subscriber who has unsubscribed.
+// returns whether the subscriber was pending, following the link twice confirms only once
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query!(
         r#"
-        UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+        UPDATE subscriptions SET status = 'confirmed'
+        WHERE id = $1 AND status = 'pending_confirmation'
         "#,
         subscriber_id,
     )
@@ -44,7 +59,7 @@ pub async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<()
         e
     })?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
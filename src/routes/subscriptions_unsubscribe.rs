@@ -0,0 +1,156 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use htmlescape::encode_minimal;
+use sqlx::PgPool;
+
+use crate::email_client::EmailHeader;
+use crate::metrics::{Metrics, SubscriptionStage};
+use crate::routes::unexpected_error_status;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    unsubscribe_token: String,
+}
+
+// the link is embedded in every issue through the '{{unsubscribe_url}}' merge field.
+// mail scanners and link prefetchers follow the links of an email, so following it only
+// shows a confirmation page: the subscriber is unsubscribed by a POST
+#[tracing::instrument(
+    name = "Show the unsubscribe confirmation page.",
+    skip(parameters, pool)
+)]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    match is_known_unsubscribe_token(&pool, &parameters.unsubscribe_token).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Unauthorized().finish(),
+        Err(e) => return HttpResponse::build(unexpected_error_status(&e)).finish(),
+    }
+    let action = encode_minimal(&format!(
+        "/subscriptions/unsubscribe?unsubscribe_token={}",
+        parameters.unsubscribe_token
+    ));
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Unsubscribe</title>
+</head>
+<body>
+    <h1>Unsubscribe</h1>
+    <p>You will no longer receive our newsletter.</p>
+    <form action="{action}" method="post">
+        <button type="submit">Unsubscribe</button>
+    </form>
+</body>
+</html>"#,
+        ))
+}
+
+// submitted by the confirmation page, or by mail clients offering one-click unsubscription
+// (RFC 8058), which send 'List-Unsubscribe=One-Click' as the body
+#[tracing::instrument(name = "Unsubscribe a subscriber.", skip(parameters, pool, metrics))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    metrics: web::Data<Metrics>,
+) -> HttpResponse {
+    match unsubscribe_subscriber(&pool, &parameters.unsubscribe_token).await {
+        Ok(Unsubscription::Unsubscribed) => {
+            metrics.record_subscription(SubscriptionStage::Unsubscribed);
+            HttpResponse::Ok().finish()
+        }
+        Ok(Unsubscription::AlreadyUnsubscribed | Unsubscription::Suppressed) => {
+            HttpResponse::Ok().finish()
+        }
+        Ok(Unsubscription::UnknownToken) => HttpResponse::Unauthorized().finish(),
+        Err(e) => HttpResponse::build(unexpected_error_status(&e)).finish(),
+    }
+}
+
+#[tracing::instrument(name = "Look up an unsubscribe token", skip_all)]
+async fn is_known_unsubscribe_token(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM subscriptions WHERE unsubscribe_token = $1
+        ) AS "exists!"
+        "#,
+        unsubscribe_token,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub enum Unsubscription {
+    Unsubscribed,
+    AlreadyUnsubscribed,
+    // the address gets no email anyway, its status keeps the reason of the suppression
+    Suppressed,
+    UnknownToken,
+}
+
+// following the link twice keeps the date of the first unsubscription
+#[tracing::instrument(
+    name = "Mark subscriber as unsubscribed",
+    skip(unsubscribe_token, pool)
+)]
+pub async fn unsubscribe_subscriber(
+    pool: &PgPool,
+    unsubscribe_token: &str,
+) -> Result<Unsubscription, sqlx::Error> {
+    // the joined row still holds the status from before the update
+    let previous = sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'unsubscribed', unsubscribed_at = COALESCE(subscriptions.unsubscribed_at, now())
+        FROM subscriptions AS previous
+        WHERE subscriptions.id = previous.id AND subscriptions.unsubscribe_token = $1
+            AND previous.status NOT IN ('suppressed')
+        RETURNING previous.status
+        "#,
+        unsubscribe_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+
+    Ok(match previous {
+        None if is_known_unsubscribe_token(pool, unsubscribe_token).await? => {
+            Unsubscription::Suppressed
+        }
+        None => Unsubscription::UnknownToken,
+        Some(row) if row.status == "unsubscribed" => Unsubscription::AlreadyUnsubscribed,
+        Some(_) => Unsubscription::Unsubscribed,
+    })
+}
+
+// builds the link resolved by the '{{unsubscribe_url}}' merge field
+pub fn unsubscribe_url(base_url: &str, unsubscribe_token: &str) -> String {
+    format!("{base_url}/subscriptions/unsubscribe?unsubscribe_token={unsubscribe_token}")
+}
+
+// lets mail clients show their own unsubscribe button, which POSTs to the link (RFC 8058)
+pub fn list_unsubscribe_headers(unsubscribe_url: &str) -> Vec<EmailHeader> {
+    vec![
+        EmailHeader {
+            name: "List-Unsubscribe".into(),
+            value: format!("<{unsubscribe_url}>"),
+        },
+        EmailHeader {
+            name: "List-Unsubscribe-Post".into(),
+            value: "List-Unsubscribe=One-Click".into(),
+        },
+    ]
+}
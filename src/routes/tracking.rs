@@ -0,0 +1,74 @@
+use actix_web::{http::header, web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::configuration::TrackingSettings;
+use crate::tracking::{verify_click_token, verify_open_token, TRACKING_PIXEL};
+
+// the pixel is served whatever happens, a broken image would only bother the reader
+#[tracing::instrument(name = "Track an email open", skip(token, pool, tracking))]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<TrackingSettings>,
+) -> HttpResponse {
+    if let Some(delivery_id) = verify_open_token(&tracking.hmac_secret, &token) {
+        if tracking.enabled {
+            if let Err(e) = record_tracking_event(&pool, delivery_id, "open", None).await {
+                tracing::error!(error.cause_chain = ?e, "Failed to record an email open.");
+            }
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(TRACKING_PIXEL)
+}
+
+// links keep working when tracking is disabled after an issue went out, they are just not logged
+#[tracing::instrument(name = "Track a link click", skip(token, pool, tracking))]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    tracking: web::Data<TrackingSettings>,
+) -> HttpResponse {
+    let Some((delivery_id, url)) = verify_click_token(&tracking.hmac_secret, &token) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if tracking.enabled {
+        if let Err(e) = record_tracking_event(&pool, delivery_id, "click", Some(&url)).await {
+            tracing::error!(error.cause_chain = ?e, "Failed to record a link click.");
+        }
+    }
+
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish()
+}
+
+#[tracing::instrument(name = "Record a tracking event", skip(pool))]
+async fn record_tracking_event(
+    pool: &PgPool,
+    delivery_id: Uuid,
+    kind: &str,
+    url: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tracking_events (event_id, delivery_id, kind, url, occurred_at)
+        VALUES ($1, $2, $3, $4, now())
+        "#,
+        Uuid::new_v4(),
+        delivery_id,
+        kind,
+        url
+    )
+    .execute(pool)
+    .await
+    .context("Failed to insert a tracking event.")?;
+
+    Ok(())
+}
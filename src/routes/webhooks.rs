@@ -0,0 +1,173 @@
+use actix_web::{http::StatusCode, web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::routes::{error_chain_fmt, unexpected_error_status};
+use crate::suppression_list::{insert_suppression, SuppressionKind, SuppressionSource};
+
+// the subset of Postmark's bounce and spam complaint webhook payloads we rely on
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkEvent {
+    record_type: String,
+    #[serde(rename = "ID")]
+    id: i64,
+    #[serde(rename = "Type")]
+    event_type: String,
+    email: String,
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    description: Option<String>,
+    details: Option<String>,
+    bounced_at: DateTime<Utc>,
+}
+
+impl PostmarkEvent {
+    // soft bounces and transient failures may resolve on their own, they are only recorded
+    fn suppression_source(&self) -> Option<SuppressionSource> {
+        if self.record_type == "SpamComplaint" {
+            Some(SuppressionSource::Complaint)
+        } else if self.event_type == "HardBounce" {
+            Some(SuppressionSource::Bounce)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Unsupported record type: {0}.")]
+    UnsupportedRecordType(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for WebhookError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            WebhookError::UnsupportedRecordType(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            WebhookError::UnexpectedError(e) => unexpected_error_status(e.as_ref()),
+        }
+    }
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(
+    name = "Ingest a Postmark webhook event",
+    skip(body, pool),
+    fields(
+        record_type = %body.record_type,
+        event_type = %body.event_type,
+        subscriber_email = %body.email
+    )
+)]
+pub async fn postmark_webhook(
+    body: web::Json<PostmarkEvent>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, WebhookError> {
+    let event = body.0;
+    if !matches!(event.record_type.as_str(), "Bounce" | "SpamComplaint") {
+        return Err(WebhookError::UnsupportedRecordType(event.record_type));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+
+    let inserted = insert_email_event(&mut transaction, &event)
+        .await
+        .context("Failed to store a Postmark webhook event.")?;
+    // a retried delivery of an event we already processed is acknowledged without side effects
+    if let Some(source) = event.suppression_source().filter(|_| inserted) {
+        suppress_subscriber(&mut transaction, &event.email)
+            .await
+            .context("Failed to suppress a subscriber.")?;
+        // the address is blocked for every kind of email, not only newsletter issues
+        if let Ok(address) = SuppressionKind::Address.parse_value(&event.email) {
+            let reason = format!(
+                "{}: {}",
+                event.event_type,
+                event.description.as_deref().unwrap_or("-")
+            );
+            insert_suppression(
+                &mut transaction,
+                SuppressionKind::Address,
+                &address,
+                &reason,
+                source,
+            )
+            .await
+            .context("Failed to add an address to the suppression list.")?;
+        }
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a Postmark webhook event.")?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_email_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &PostmarkEvent,
+) -> Result<bool, sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            event_id,
+            postmark_id,
+            record_type,
+            event_type,
+            email,
+            message_id,
+            description,
+            details,
+            occurred_at,
+            received_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, now())
+        ON CONFLICT (record_type, postmark_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        event.id,
+        event.record_type,
+        event.event_type,
+        event.email,
+        event.message_id,
+        event.description,
+        event.details,
+        event.bounced_at
+    );
+
+    Ok(transaction.execute(query).await?.rows_affected() > 0)
+}
+
+// Postmark may report the address with a different casing than the one we stored
+#[tracing::instrument(skip(transaction))]
+async fn suppress_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'suppressed'
+        WHERE lower(email) = lower($1)
+        "#,
+        email
+    );
+    transaction.execute(query).await?;
+
+    Ok(())
+}
@@ -1,36 +1,62 @@
+use crate::authentication::{
+    create_initial_admin, reject_anonymous_users, reject_unknown_metrics_scrapers,
+    reject_unknown_webhook_senders,
+};
 use crate::configuration::{DatabaseSettings, Settings};
 use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletter, subscribe};
+use crate::metrics::{track_http_requests, Metrics};
+use crate::migrations::run_migrations;
+use crate::rate_limiting::{rate_limit, RateLimiter};
+use crate::routes::{
+    add_suppression, archive, archive_feed, archived_issue, cancel_newsletter_schedule, confirm,
+    create_draft, delete_draft, delete_suppression, export_metrics, get_log_filter,
+    get_newsletter_issue, health_check, health_ready, issue_report, list_issues, list_suppressions,
+    postmark_webhook, preview_issue, publish_draft, publish_newsletter, schedule_newsletter,
+    send_test_issue, subscribe, subscription_challenge, track_click, track_open, unsubscribe,
+    unsubscribe_form, update_draft, update_log_filter, WebViewCache,
+};
+use crate::telemetry::LogFilter;
 use actix_web::dev::Server;
+use actix_web::middleware::from_fn;
 use actix_web::web::Data;
 use actix_web::{web, App, HttpServer};
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
     port: u16,
     server: Server,
+    db_pool: PgPool,
+    read_pool: ReadPool,
+    shutdown: CancellationToken,
+    shutdown_drain: Duration,
 }
 
 impl Application {
     // the build function is a construct for the application, so all necessary data is passed
-    pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
+    // the 'EmailClient' is shared with the delivery workers, so they draw on the same provider limits
+    // cancelling 'shutdown' stops the application gracefully
+    pub async fn build(
+        configuration: Settings,
+        email_client: EmailClient,
+        log_filter: LogFilter,
+        shutdown: CancellationToken,
+    ) -> Result<Self, std::io::Error> {
+        // the instance only serves once its schema is up to date
+        if configuration.database.run_migrations_on_startup {
+            run_migrations(&configuration.database)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
         let connection_pool = get_connection_pool(&configuration.database);
-
-        // build an 'EmailClient' using 'configuration'
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        create_initial_admin(&connection_pool, &configuration.initial_admin)
+            .await
+            .map_err(std::io::Error::other)?;
+        let read_pool = get_read_pool(&configuration.database, &connection_pool);
 
         let address = format!(
             "{}:{}",
@@ -38,14 +64,27 @@ impl Application {
         );
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let metrics = Metrics::new().map_err(std::io::Error::other)?;
+        let shutdown_drain = Duration::from_secs(configuration.application.shutdown_drain_seconds);
         let server = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
+            read_pool.clone(),
             email_client,
-            configuration.application.base_url,
+            metrics,
+            log_filter,
+            shutdown.clone(),
+            configuration,
         )?;
 
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            db_pool: connection_pool,
+            read_pool,
+            shutdown,
+            shutdown_drain,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -54,7 +93,25 @@ impl Application {
 
     // this function only returns when the application is stopped
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server_handle = self.server.handle();
+        let shutdown = self.shutdown.clone();
+        let shutdown_drain = self.shutdown_drain;
+        tokio::spawn(async move {
+            shutdown.cancelled().await;
+            // the readiness check is already failing, the server keeps serving while it drains
+            tracing::info!(
+                "Shutting down, the server stops accepting connections in {:?}.",
+                shutdown_drain
+            );
+            tokio::time::sleep(shutdown_drain).await;
+            // waits for the requests in flight, up to the grace period
+            server_handle.stop(true).await;
+        });
+
+        let outcome = self.server.await;
+        self.db_pool.close().await;
+        self.read_pool.0.close().await;
+        outcome
     }
 }
 
@@ -63,32 +120,171 @@ impl Application {
 //      expose us to conflicts
 pub struct ApplicationBaseUrl(pub String);
 
+// the pool serving the read-only queries that can tolerate replication lag, so they do not
+// compete with signups on the primary. writes, and reads that must see them, use the 'PgPool'
+#[derive(Clone)]
+pub struct ReadPool(pub PgPool);
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
-    PgPoolOptions::new().connect_lazy_with(configuration.with_db())
+    PgPoolOptions::new()
+        .min_connections(configuration.min_connections)
+        .max_connections(configuration.max_connections)
+        .acquire_timeout(configuration.acquire_timeout())
+        .idle_timeout(configuration.idle_timeout())
+        .max_lifetime(configuration.max_lifetime())
+        .connect_lazy_with(configuration.with_db())
+}
+
+// without a replica, the reads share the primary pool
+pub fn get_read_pool(configuration: &DatabaseSettings, primary: &PgPool) -> ReadPool {
+    match configuration.read_replica() {
+        Some(replica) => ReadPool(get_connection_pool(&replica)),
+        None => ReadPool(primary.clone()),
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     db_pool: PgPool,
+    read_pool: ReadPool,
     email_client: EmailClient,
-    base_url: String,
+    metrics: Metrics,
+    log_filter: LogFilter,
+    shutdown: CancellationToken,
+    configuration: Settings,
 ) -> Result<Server, std::io::Error> {
+    let shutdown_grace_period = configuration.application.shutdown_grace_period_seconds;
+    // shared by all the workers, so the buckets are the same whichever worker serves a request
+    let rate_limiter = Data::new(RateLimiter::new(configuration.rate_limit, db_pool.clone()));
     // wrap the connection in a smart pointer (Arc, giving each instance of the app a pointer to the connection)
     let db_pool = web::Data::new(db_pool);
+    let read_pool = Data::new(read_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let metrics = Data::new(metrics);
+    let log_filter = Data::new(log_filter);
+    let shutdown = Data::new(shutdown);
+    let base_url = Data::new(ApplicationBaseUrl(configuration.application.base_url));
+    let tracking = Data::new(configuration.tracking);
+    let webhooks = Data::new(configuration.webhooks);
+    let metrics_scraper = Data::new(configuration.metrics);
+    let bot_protection = Data::new(configuration.bot_protection);
+    let email_policy = Data::new(configuration.email_policy.policy());
+    let email_domain_checker = Data::new(configuration.email_domain_check.checker());
+    let web_views = Data::new(WebViewCache::default());
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
+            .wrap(from_fn(track_http_requests))
             .route("/health_check", web::get().to(health_check))
-            .route("/newsletters", web::post().to(publish_newsletter))
-            .route("/subscriptions", web::post().to(subscribe))
-            .route("/subscriptions/confirm", web::get().to(confirm))
+            .route("/health/ready", web::get().to(health_ready))
+            .service(
+                web::resource("/metrics")
+                    .wrap(from_fn(reject_unknown_metrics_scrapers))
+                    .route(web::get().to(export_metrics)),
+            )
+            .route("/archive", web::get().to(archive))
+            // registered before '/archive/{slug}', which would match it otherwise
+            .route("/archive/feed.xml", web::get().to(archive_feed))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .service(
+                web::scope("/admin")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("/issues", web::get().to(list_issues))
+                    .route("/issues", web::post().to(create_draft))
+                    .route(
+                        "/issues/{newsletter_issue_id}",
+                        web::get().to(get_newsletter_issue),
+                    )
+                    .route("/issues/{newsletter_issue_id}", web::put().to(update_draft))
+                    .route(
+                        "/issues/{newsletter_issue_id}",
+                        web::delete().to(delete_draft),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/publish",
+                        web::post().to(publish_draft),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/preview",
+                        web::get().to(preview_issue),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/report",
+                        web::get().to(issue_report),
+                    )
+                    .route(
+                        "/issues/{newsletter_issue_id}/test",
+                        web::post().to(send_test_issue),
+                    )
+                    .route("/log_filter", web::get().to(get_log_filter))
+                    .route("/log_filter", web::put().to(update_log_filter))
+                    .route("/suppressions", web::get().to(list_suppressions))
+                    .route("/suppressions", web::post().to(add_suppression))
+                    .route(
+                        "/suppressions/{entry_id}",
+                        web::delete().to(delete_suppression),
+                    ),
+            )
+            .service(
+                web::scope("/newsletters")
+                    .wrap(from_fn(reject_anonymous_users))
+                    .route("", web::post().to(publish_newsletter))
+                    .route(
+                        "/{newsletter_issue_id}/schedule",
+                        web::put().to(schedule_newsletter),
+                    )
+                    .route(
+                        "/{newsletter_issue_id}/schedule",
+                        web::delete().to(cancel_newsletter_schedule),
+                    ),
+            )
+            .service(
+                web::scope("/webhooks")
+                    .wrap(from_fn(reject_unknown_webhook_senders))
+                    .route("/postmark", web::post().to(postmark_webhook)),
+            )
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .service(
+                web::resource("/subscriptions")
+                    .wrap(from_fn(rate_limit))
+                    .route(web::post().to(subscribe)),
+            )
+            .route(
+                "/subscriptions/challenge",
+                web::get().to(subscription_challenge),
+            )
+            .service(
+                web::resource("/subscriptions/confirm")
+                    .wrap(from_fn(rate_limit))
+                    .route(web::get().to(confirm)),
+            )
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
             // register the connection as part of the application state
             .app_data(db_pool.clone())
+            .app_data(read_pool.clone())
             .app_data(email_client.clone())
+            .app_data(metrics.clone())
+            .app_data(log_filter.clone())
+            .app_data(shutdown.clone())
             .app_data(base_url.clone())
+            .app_data(tracking.clone())
+            .app_data(webhooks.clone())
+            .app_data(metrics_scraper.clone())
+            .app_data(rate_limiter.clone())
+            .app_data(bot_protection.clone())
+            .app_data(email_policy.clone())
+            .app_data(email_domain_checker.clone())
+            .app_data(web_views.clone())
     })
+    // signals are handled by the caller, which cancels 'shutdown' to stop the server
+    .disable_signals()
+    .shutdown_timeout(shutdown_grace_period)
     .listen(listener)?
     .run();
 
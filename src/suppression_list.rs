@@ -0,0 +1,175 @@
+use crate::domain::SubscriberEmail;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// every caller of the 'EmailClient' consults the list before sending,
+// whatever the status of the recipient in 'subscriptions'
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionKind {
+    Address,
+    Domain,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionSource {
+    Manual,
+    Bounce,
+    Complaint,
+    LegalRequest,
+}
+
+impl SuppressionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionKind::Address => "address",
+            SuppressionKind::Domain => "domain",
+        }
+    }
+
+    // entries are matched case-insensitively, so they are stored lowercase
+    pub fn parse_value(&self, value: &str) -> Result<String, String> {
+        let value = value.trim().to_lowercase();
+        let valid = match self {
+            SuppressionKind::Address => SubscriberEmail::parse(value.clone()).is_ok(),
+            SuppressionKind::Domain => {
+                value.contains('.')
+                    && !value.starts_with('.')
+                    && !value.ends_with('.')
+                    && value
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+            }
+        };
+
+        if valid {
+            Ok(value)
+        } else {
+            Err(format!("{value} is not a valid {}.", self.as_str()))
+        }
+    }
+}
+
+impl SuppressionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionSource::Manual => "manual",
+            SuppressionSource::Bounce => "bounce",
+            SuppressionSource::Complaint => "complaint",
+            SuppressionSource::LegalRequest => "legal_request",
+        }
+    }
+}
+
+fn email_domain(email: &str) -> &str {
+    email.rsplit_once('@').map_or("", |(_, domain)| domain)
+}
+
+// the domain of the address and every parent domain, so an entry for 'example.com' also covers
+// 'mail.example.com'
+fn suppressible_domains(email: &str) -> Vec<&str> {
+    let domain = email_domain(email);
+    std::iter::once(domain)
+        .chain(domain.match_indices('.').map(|(i, _)| &domain[i + 1..]))
+        .filter(|domain| !domain.is_empty())
+        .collect()
+}
+
+#[tracing::instrument(name = "Check the suppression list", skip(pool, email))]
+pub async fn is_suppressed(pool: &PgPool, email: &SubscriberEmail) -> Result<bool, sqlx::Error> {
+    let email = email.as_ref().to_lowercase();
+    let r = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM suppression_list
+            WHERE (kind = 'address' AND value = $1) OR (kind = 'domain' AND value = ANY($2))
+        ) AS "suppressed!"
+        "#,
+        email,
+        &suppressible_domains(&email) as &[&str]
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(r.suppressed)
+}
+
+// returns 'None' if an identical entry is already on the list
+#[tracing::instrument(skip(transaction, value))]
+pub async fn insert_suppression(
+    transaction: &mut Transaction<'_, Postgres>,
+    kind: SuppressionKind,
+    value: &str,
+    reason: &str,
+    source: SuppressionSource,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let entry_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO suppression_list (entry_id, kind, value, reason, source, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT (kind, value) DO NOTHING
+        "#,
+        entry_id,
+        kind.as_str(),
+        value,
+        reason,
+        source.as_str()
+    );
+    let inserted = transaction.execute(query).await?.rows_affected() > 0;
+
+    Ok(inserted.then_some(entry_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{email_domain, suppressible_domains, SuppressionKind};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn parent_domains_are_suppressible() {
+        assert_eq!(
+            suppressible_domains("ursula@mail.example.com"),
+            vec!["mail.example.com", "example.com", "com"]
+        );
+        assert_eq!(suppressible_domains("ursula"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn values_are_lowercased() {
+        assert_ok_eq!(
+            SuppressionKind::Address.parse_value(" Ursula@Example.com "),
+            "ursula@example.com".to_string()
+        );
+        assert_ok_eq!(
+            SuppressionKind::Domain.parse_value("Example.COM"),
+            "example.com".to_string()
+        );
+    }
+
+    #[test]
+    fn invalid_domains_are_rejected() {
+        for domain in [
+            "",
+            "localhost",
+            ".example.com",
+            "example.com.",
+            "ursula@example.com",
+        ] {
+            assert_err!(SuppressionKind::Domain.parse_value(domain));
+        }
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        assert_err!(SuppressionKind::Address.parse_value("example.com"));
+    }
+
+    #[test]
+    fn the_domain_is_taken_after_the_last_at_sign() {
+        assert_eq!(email_domain("\"a@b\"@example.com"), "example.com");
+        assert_eq!(email_domain("not-an-email"), "");
+    }
+}
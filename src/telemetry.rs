@@ -1,28 +1,550 @@
+use crate::configuration::TelemetrySettings;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use linkify::{LinkFinder, LinkKind};
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_http::HeaderInjector;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use reqwest::header::HeaderMap;
+use secrecy::{ExposeSecret, Secret};
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
 use tracing::{dispatcher::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
+use tracing_subscriber::{layer::SubscriberExt, reload, EnvFilter, Registry};
 
 // use of 'Subscriber' type because the actual type of subscriber is more complex
-// need to implement Send + Sync to make it possible to pass it into 'init_subscriber'
+// need to implement Send + Sync to make it possible to pass it into 'init_subscriber'.
+// with a 'tracer', the spans are exported as well as logged. the returned 'LogFilter'
+// changes the filter of this subscriber while it runs
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+    redaction: RedactionPolicy,
+    tracer: Option<Tracer>,
+) -> (impl Subscriber + Send + Sync, LogFilter)
 where
     // weird syntax -> means that Sink implements 'MakeWriter' for all choices of the lifetime parameter `'a`
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
-    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+    let formatting_layer =
+        BunyanFormattingLayer::new(name, RedactingMakeWriter::new(sink, redaction));
 
-    Registry::default()
+    let subscriber = Registry::default()
         .with(env_filter)
         .with(JsonStorageLayer)
         .with(formatting_layer)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
+    let log_filter = LogFilter {
+        handle,
+        pending_revert: Arc::default(),
+        revert_ids: Arc::default(),
+    };
+
+    (subscriber, log_filter)
+}
+
+// clones change the same filter
+#[derive(Clone)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    pending_revert: Arc<Mutex<Option<PendingRevert>>>,
+    revert_ids: Arc<AtomicU64>,
+}
+
+struct PendingRevert {
+    // a revert task only applies the pending revert it was spawned for: an aborted task may
+    // already be running when a later change replaces it
+    id: u64,
+    directive: String,
+    reverts_at: DateTime<Utc>,
+    task: JoinHandle<()>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct LogFilterState {
+    pub directive: String,
+    pub reverts_to: Option<String>,
+    pub reverts_at: Option<DateTime<Utc>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LogFilterError {
+    #[error("'{0}' is not a valid filter directive.")]
+    InvalidDirective(String),
+    #[error("The log filter cannot be reverted that far in the future.")]
+    RevertTooLate,
+    #[error(transparent)]
+    ReloadFailed(#[from] reload::Error),
+}
+
+impl LogFilter {
+    pub fn state(&self) -> LogFilterState {
+        let pending_revert = self.pending_revert.lock().unwrap();
+        LogFilterState {
+            directive: self.directive(),
+            reverts_to: pending_revert.as_ref().map(|p| p.directive.clone()),
+            reverts_at: pending_revert.as_ref().map(|p| p.reverts_at),
+        }
+    }
+
+    // with 'revert_after', the directive only holds for a while, e.g. to debug an incident
+    // without leaving the logs verbose. a later change replaces it, but reverts to the same
+    // directive if it is temporary as well
+    pub fn set(
+        &self,
+        directive: &str,
+        revert_after: Option<Duration>,
+    ) -> Result<(), LogFilterError> {
+        let filter = EnvFilter::try_new(directive)
+            .map_err(|_| LogFilterError::InvalidDirective(directive.to_owned()))?;
+        // checked before anything changes, the filter stays as it is on failure
+        let reverts_at = revert_after
+            .map(|revert_after| {
+                chrono::Duration::from_std(revert_after)
+                    .ok()
+                    .and_then(|revert_after| Utc::now().checked_add_signed(revert_after))
+                    .ok_or(LogFilterError::RevertTooLate)
+            })
+            .transpose()?;
+
+        let mut pending_revert = self.pending_revert.lock().unwrap();
+        let previous = match pending_revert.take() {
+            Some(pending) => {
+                pending.task.abort();
+                pending.directive
+            }
+            None => self.directive(),
+        };
+        self.handle.reload(filter)?;
+        tracing::info!(directive, "Changed the log filter.");
+
+        if let (Some(revert_after), Some(reverts_at)) = (revert_after, reverts_at) {
+            let id = self.revert_ids.fetch_add(1, Ordering::Relaxed);
+            let log_filter = self.clone();
+            let task = tokio::spawn(async move {
+                tokio::time::sleep(revert_after).await;
+                log_filter.revert(id);
+            });
+            *pending_revert = Some(PendingRevert {
+                id,
+                directive: previous,
+                reverts_at,
+                task,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn directive(&self) -> String {
+        self.handle
+            .with_current(|filter| filter.to_string())
+            .unwrap_or_default()
+    }
+
+    // the lock is held until the filter is reloaded, so a concurrent change cannot be undone
+    fn revert(&self, id: u64) {
+        let mut pending_revert = self.pending_revert.lock().unwrap();
+        let Some(pending) = pending_revert.take_if(|pending| pending.id == id) else {
+            return;
+        };
+        // the directive was in use before, it is valid
+        let outcome = EnvFilter::try_new(&pending.directive)
+            .map_err(|_| LogFilterError::InvalidDirective(pending.directive.clone()))
+            .and_then(|filter| Ok(self.handle.reload(filter)?));
+        match outcome {
+            Ok(()) => tracing::info!(directive = %pending.directive, "Reverted the log filter."),
+            Err(e) => tracing::error!(error.message = %e, "Failed to revert the log filter."),
+        }
+    }
+}
+
+// must be called from within a tokio runtime, spans are exported in batches by a background task
+pub fn init_tracing_export(
+    name: &str,
+    settings: &TelemetrySettings,
+    redaction: RedactionPolicy,
+) -> Result<Option<Tracer>, TraceError> {
+    // the W3C 'traceparent' header is read from incoming requests and added to outgoing ones
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let Some(provider) = tracer_provider(name, settings, redaction)? else {
+        return Ok(None);
+    };
+    let tracer = provider.tracer(name.to_owned());
+    global::set_tracer_provider(provider);
+
+    Ok(Some(tracer))
+}
+
+// flushes the spans that have not been exported yet
+pub fn shutdown_tracing_export() {
+    global::shutdown_tracer_provider();
+}
+
+fn tracer_provider(
+    name: &str,
+    settings: &TelemetrySettings,
+    redaction: RedactionPolicy,
+) -> Result<Option<TracerProvider>, TraceError> {
+    let Some(endpoint) = &settings.otlp_endpoint else {
+        return Ok(None);
+    };
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .with_timeout(Duration::from_millis(settings.export_timeout_milliseconds))
+        .build()?;
+    let exporter = RedactingSpanExporter {
+        inner: exporter,
+        policy: redaction,
+    };
+
+    Ok(Some(
+        TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new(
+                "service.name",
+                name.to_owned(),
+            )]))
+            .build(),
+    ))
+}
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+// span fields are exported as attributes, they go through the same redaction as the logs
+#[derive(Debug)]
+struct RedactingSpanExporter<E> {
+    inner: E,
+    policy: RedactionPolicy,
+}
+
+impl<E: SpanExporter> SpanExporter for RedactingSpanExporter<E> {
+    fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<ExportResult> {
+        for span in batch.iter_mut() {
+            self.policy.redact_attributes(&mut span.attributes);
+            for event in span.events.events.iter_mut() {
+                // the name of an event is its log message
+                event.name = self.policy.scrub(&event.name).into();
+                self.policy.redact_attributes(&mut event.attributes);
+            }
+        }
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<ExportResult> {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+// the headers carrying the current span to the services we call, so their spans join our traces
+pub fn trace_context_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+// sensitive values are redacted from each bunyan record before it is written out, and from
+// the attributes and events of the spans exported over OTLP
+#[derive(Clone, Debug)]
+pub struct RedactionPolicy {
+    masked_fields: HashSet<String>,
+    hashed_fields: HashSet<String>,
+    removed_fields: HashSet<String>,
+    // fields holding a URL, e.g. the 'http.target' of the request spans
+    url_fields: HashSet<String>,
+    // route templates such as '/t/c/{token}', whose segments in braces are masked in URLs
+    secret_paths: Vec<String>,
+    // catches the addresses that end up in error chains and debug output
+    scrub_emails_in_text: bool,
+    hash_key: Secret<String>,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self::new(&[], &[], &[], false)
+    }
+}
+
+impl RedactionPolicy {
+    pub fn new(
+        masked_fields: &[String],
+        hashed_fields: &[String],
+        removed_fields: &[String],
+        scrub_emails_in_text: bool,
+    ) -> Self {
+        Self {
+            masked_fields: masked_fields.iter().cloned().collect(),
+            hashed_fields: hashed_fields.iter().cloned().collect(),
+            removed_fields: removed_fields.iter().cloned().collect(),
+            url_fields: HashSet::new(),
+            secret_paths: Vec::new(),
+            scrub_emails_in_text,
+            hash_key: Secret::new(String::new()),
+        }
+    }
+
+    // the values of the query parameters of these URLs are masked, tokens are passed that way
+    pub fn with_urls(mut self, url_fields: &[String], secret_paths: &[String]) -> Self {
+        self.url_fields = url_fields.iter().cloned().collect();
+        self.secret_paths = secret_paths.to_vec();
+        self
+    }
+
+    // without a secret key, the hashes of guessable values such as names could be reversed by
+    // hashing guesses
+    pub fn with_hash_key(mut self, hash_key: Secret<String>) -> Self {
+        self.hash_key = hash_key;
+        self
+    }
+
+    fn redact_line(&self, line: &[u8]) -> Vec<u8> {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let mut redacted = match serde_json::from_slice::<Value>(content) {
+            Ok(mut record) => {
+                self.redact_value(&mut record);
+                serde_json::to_vec(&record).expect("A JSON value can always be serialized.")
+            }
+            // not a bunyan record, only its text can be scrubbed
+            Err(_) => self.scrub(&String::from_utf8_lossy(content)).into_bytes(),
+        };
+        redacted.push(b'\n');
+        redacted
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                fields.retain(|key, _| !self.removed_fields.contains(key));
+                for (key, value) in fields.iter_mut() {
+                    if self.masked_fields.contains(key) {
+                        *value = Value::String(mask(&as_text(value)));
+                    } else if self.hashed_fields.contains(key) {
+                        *value = Value::String(self.hash(&as_text(value)));
+                    } else if self.url_fields.contains(key) {
+                        *value = Value::String(self.scrub(&self.redact_url(&as_text(value))));
+                    } else {
+                        self.redact_value(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact_value(value)),
+            Value::String(text) => *text = self.scrub(text),
+            _ => {}
+        }
+    }
+
+    fn redact_attributes(&self, attributes: &mut Vec<KeyValue>) {
+        attributes.retain(|attribute| !self.removed_fields.contains(attribute.key.as_str()));
+        for attribute in attributes.iter_mut() {
+            let key = attribute.key.as_str();
+            if self.masked_fields.contains(key) {
+                attribute.value = mask(&attribute.value.as_str()).into();
+            } else if self.hashed_fields.contains(key) {
+                attribute.value = self.hash(&attribute.value.as_str()).into();
+            } else if self.url_fields.contains(key) {
+                let url = self.redact_url(&attribute.value.as_str());
+                attribute.value = self.scrub(&url).into();
+            } else if let opentelemetry::Value::String(text) = &attribute.value {
+                attribute.value = self.scrub(text.as_str()).into();
+            }
+        }
+    }
+
+    // the same value always gives the same hash, so records can still be correlated
+    fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hash_key.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size.");
+        mac.update(value.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+        format!("hmac:{hex}")
+    }
+
+    // '/subscriptions/confirm?subscription_token=abc' becomes
+    // '/subscriptions/confirm?subscription_token=***'
+    fn redact_url(&self, url: &str) -> String {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, _)) => (url, true),
+            None => (url, false),
+        };
+        let (url, query) = match url.split_once('?') {
+            Some((url, query)) => (url, Some(query)),
+            None => (url, None),
+        };
+        // absolute URLs start with a scheme and a host, the path comes after them
+        let path_start = url
+            .find("://")
+            .map(|i| url[i + 3..].find('/').map_or(url.len(), |j| i + 3 + j))
+            .unwrap_or(0);
+        let (origin, path) = url.split_at(path_start);
+
+        let mut redacted = format!("{origin}{}", self.redact_path(path));
+        if let Some(query) = query {
+            let parameters: Vec<String> = query
+                .split('&')
+                .map(|parameter| match parameter.split_once('=') {
+                    Some((key, _)) => format!("{key}=***"),
+                    None => parameter.to_owned(),
+                })
+                .collect();
+            redacted.push('?');
+            redacted.push_str(&parameters.join("&"));
+        }
+        if fragment {
+            redacted.push_str("#***");
+        }
+        redacted
+    }
+
+    fn redact_path(&self, path: &str) -> String {
+        let segments: Vec<&str> = path.split('/').collect();
+        for template in &self.secret_paths {
+            let template: Vec<&str> = template.split('/').collect();
+            let is_secret = |segment: &&str| segment.starts_with('{') && segment.ends_with('}');
+            if template.len() == segments.len()
+                && template
+                    .iter()
+                    .zip(&segments)
+                    .all(|(expected, segment)| is_secret(expected) || expected == segment)
+            {
+                return template
+                    .iter()
+                    .zip(&segments)
+                    .map(|(expected, segment)| if is_secret(expected) { "***" } else { segment })
+                    .collect::<Vec<_>>()
+                    .join("/");
+            }
+        }
+        path.to_owned()
+    }
+
+    fn scrub(&self, text: &str) -> String {
+        if !self.scrub_emails_in_text {
+            return text.to_owned();
+        }
+        let mut finder = LinkFinder::new();
+        finder.kinds(&[LinkKind::Email]);
+
+        let mut scrubbed = String::with_capacity(text.len());
+        for span in finder.spans(text) {
+            match span.kind() {
+                Some(LinkKind::Email) => scrubbed.push_str(&mask(span.as_str())),
+                _ => scrubbed.push_str(span.as_str()),
+            }
+        }
+        scrubbed
+    }
+}
+
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+// keeps enough to tell addresses apart while debugging: 'ursula@gmail.com' becomes 'u***@gmail.com'
+fn mask(value: &str) -> String {
+    let (local_part, domain) = match value.rsplit_once('@') {
+        Some((local_part, domain)) => (local_part, Some(domain)),
+        None => (value, None),
+    };
+    let mut masked: String = local_part.chars().take(1).collect();
+    masked.push_str("***");
+    if let Some(domain) = domain {
+        masked.push('@');
+        masked.push_str(domain);
+    }
+    masked
+}
+
+// sits between the bunyan layer and the sink
+pub struct RedactingMakeWriter<Sink> {
+    sink: Sink,
+    policy: RedactionPolicy,
+}
+
+impl<Sink> RedactingMakeWriter<Sink> {
+    pub fn new(sink: Sink, policy: RedactionPolicy) -> Self {
+        Self { sink, policy }
+    }
+}
+
+impl<'a, Sink: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<Sink> {
+    type Writer = RedactingWriter<'a, Sink::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            inner: self.sink.make_writer(),
+            policy: &self.policy,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+// records are redacted a whole line at a time, however the bytes are handed over
+pub struct RedactingWriter<'a, W: Write> {
+    inner: W,
+    policy: &'a RedactionPolicy,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for RedactingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.inner.write_all(&self.policy.redact_line(&line))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactingWriter<'_, W> {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            let _ = self.inner.write_all(&self.policy.redact_line(&line));
+        }
+    }
 }
 
 // register a subscriber as a global default to process span data
@@ -31,3 +553,439 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger.");
     set_global_default(subscriber.into()).expect("Failed to set subscriber");
 }
+
+// blocking tasks run on a separate thread pool: the current span is attached to them explicitly,
+// otherwise their logs would not be correlated with the request that spawned them
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        get_subscriber, trace_context_headers, tracer_provider, LogFilterError,
+        RedactingMakeWriter, RedactionPolicy,
+    };
+    use crate::configuration::{get_configuration, TelemetrySettings};
+    use actix_web::{web, App, HttpResponse};
+    use opentelemetry::global;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use secrecy::Secret;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tracing_actix_web::TracingLogger;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::fmt::MakeWriter;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_collector() {
+        let collector = MockServer::start().await;
+        Mock::given(path("/v1/traces"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1..)
+            .mount(&collector)
+            .await;
+        let settings = TelemetrySettings {
+            otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+            export_timeout_milliseconds: 1000,
+        };
+        let provider = tracer_provider("test", &settings, policy())
+            .unwrap()
+            .unwrap();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            Some(provider.tracer("test")),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "Adding a new subscriber.",
+                subscriber_email = "ursula_le_guin@gmail.com"
+            )
+            .in_scope(|| {
+                tracing::info!("Sending a confirmation email to ursula_le_guin@gmail.com.");
+            });
+        });
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        // the spans are protobuf encoded, their strings are still visible in the raw body
+        let body = &collector.received_requests().await.unwrap()[0].body;
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("Adding a new subscriber."));
+        assert!(body.contains("u***@gmail.com"));
+        assert!(!body.contains("ursula_le_guin"));
+    }
+
+    #[test]
+    fn no_spans_are_exported_without_a_collector() {
+        let settings = TelemetrySettings {
+            otlp_endpoint: None,
+            export_timeout_milliseconds: 1000,
+        };
+
+        assert!(tracer_provider("test", &settings, policy())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn outgoing_requests_carry_the_trace_of_the_current_span() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = TracerProvider::builder().build().tracer("test");
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            Some(tracer),
+        );
+
+        let (trace_id, headers) = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("Send a confirmation email");
+            let _entered = span.enter();
+            let trace_id = span.context().span().span_context().trace_id();
+            (trace_id, trace_context_headers())
+        });
+
+        let traceparent = headers["traceparent"].to_str().unwrap();
+        assert!(traceparent.contains(&trace_id.to_string()));
+    }
+
+    // collects everything written to the sink, so the records can be inspected
+    #[derive(Clone, Default)]
+    struct CapturedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedSink {
+        fn records(&self) -> Vec<serde_json::Value> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            output
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    fn policy() -> RedactionPolicy {
+        RedactionPolicy::new(
+            &["subscriber_email".into()],
+            &["subscriber_name".into()],
+            &["subscription_token".into()],
+            true,
+        )
+    }
+
+    fn log_a_subscription(sink: &CapturedSink, policy: RedactionPolicy) {
+        let sink = sink.clone();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            move || sink.clone(),
+            policy,
+            None,
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!(
+                "Adding a new subscriber.",
+                subscriber_email = "ursula_le_guin@gmail.com",
+                subscriber_name = "le guin",
+                subscription_token = "mF4t2n1pQ8xZ",
+            )
+            .in_scope(|| {
+                tracing::error!(
+                    error.cause_chain = "Failed to insert ursula_le_guin@gmail.com: duplicate key",
+                    "Failed to store a new subscriber."
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn configured_fields_are_redacted_before_reaching_the_sink() {
+        let sink = CapturedSink::default();
+
+        log_a_subscription(&sink, policy());
+
+        let records = sink.records();
+        assert!(!records.is_empty());
+        for record in &records {
+            assert_eq!(record["subscriber_email"], "u***@gmail.com");
+            assert!(record["subscriber_name"]
+                .as_str()
+                .unwrap()
+                .starts_with("hmac:"));
+            assert!(record.get("subscription_token").is_none());
+        }
+        // hashing is deterministic, records about the same subscriber can still be matched
+        assert_eq!(records[0]["subscriber_name"], records[1]["subscriber_name"]);
+        let raw = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        assert!(!raw.contains("ursula_le_guin"));
+        assert!(!raw.contains("le guin"));
+        assert!(!raw.contains("mF4t2n1pQ8xZ"));
+    }
+
+    #[test]
+    fn emails_in_error_chains_are_scrubbed() {
+        let sink = CapturedSink::default();
+
+        log_a_subscription(&sink, policy());
+
+        let event = sink
+            .records()
+            .into_iter()
+            // bunyan's error level
+            .find(|record| record["level"] == 50)
+            .unwrap();
+        assert_eq!(
+            event["error.cause_chain"],
+            "Failed to insert u***@gmail.com: duplicate key"
+        );
+    }
+
+    #[test]
+    fn nothing_is_redacted_by_the_default_policy() {
+        let sink = CapturedSink::default();
+
+        log_a_subscription(&sink, RedactionPolicy::default());
+
+        let records = sink.records();
+        assert_eq!(records[0]["subscriber_email"], "ursula_le_guin@gmail.com");
+        assert_eq!(records[0]["subscription_token"], "mF4t2n1pQ8xZ");
+    }
+
+    #[test]
+    fn records_split_across_writes_are_redacted_whole() {
+        let sink = CapturedSink::default();
+        let captured = sink.clone();
+        let make_writer = RedactingMakeWriter::new(move || captured.clone(), policy());
+
+        let mut writer = make_writer.make_writer();
+        writer
+            .write_all(br#"{"msg":"hello","subscriber_email":"ursula"#)
+            .unwrap();
+        writer.write_all(b"@gmail.com\"}\n").unwrap();
+        drop(writer);
+
+        assert_eq!(sink.records()[0]["subscriber_email"], "u***@gmail.com");
+    }
+
+    #[test]
+    fn query_values_and_secret_path_segments_are_masked_in_urls() {
+        let policy = policy().with_urls(&[], &["/t/c/{token}".into()]);
+
+        assert_eq!(
+            policy.redact_url("/subscriptions/confirm?subscription_token=mF4t2n1pQ8xZ&flag"),
+            "/subscriptions/confirm?subscription_token=***&flag"
+        );
+        assert_eq!(policy.redact_url("/t/c/mF4t2n1pQ8xZ"), "/t/c/***");
+        assert_eq!(
+            policy.redact_url("https://example.com/t/c/mF4t2n1pQ8xZ?a=b#c"),
+            "https://example.com/t/c/***?a=***#***"
+        );
+        assert_eq!(
+            policy.redact_url("/archive/first-issue"),
+            "/archive/first-issue"
+        );
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        let policy = policy();
+        let keyed = policy.clone().with_hash_key(Secret::new("secret".into()));
+
+        assert_eq!(keyed.hash("le guin"), keyed.hash("le guin"));
+        assert_ne!(keyed.hash("le guin"), policy.hash("le guin"));
+    }
+
+    #[actix_web::test]
+    async fn tokens_in_request_urls_do_not_reach_the_sink() {
+        let sink = CapturedSink::default();
+        let captured = sink.clone();
+        let policy = get_configuration()
+            .expect("Failed to read configuration.")
+            .redaction
+            .policy();
+        let (subscriber, _) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            move || captured.clone(),
+            policy,
+            None,
+        );
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = actix_web::test::init_service(
+            App::new()
+                .wrap(TracingLogger::default())
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for uri in [
+            "/subscriptions/confirm?subscription_token=mF4t2n1pQ8xZ",
+            "/subscriptions/unsubscribe?unsubscribe_token=mF4t2n1pQ8xZ",
+            "/t/c/mF4t2n1pQ8xZ",
+        ] {
+            let request = actix_web::test::TestRequest::get().uri(uri).to_request();
+            actix_web::test::call_service(&app, request).await;
+        }
+
+        let records = sink.records();
+        assert!(records
+            .iter()
+            .any(|r| r["http.target"] == "/subscriptions/confirm?subscription_token=***"));
+        assert!(records.iter().any(|r| r["http.target"] == "/t/c/***"));
+        let raw = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        assert!(!raw.contains("mF4t2n1pQ8xZ"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_temporary_log_filter_reverts_after_its_timeout() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+
+        log_filter
+            .set("debug", Some(Duration::from_secs(60)))
+            .unwrap();
+        assert_eq!(log_filter.state().directive, "debug");
+        assert_eq!(log_filter.state().reverts_to.as_deref(), Some("info"));
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+
+        let state = log_filter.state();
+        assert_eq!(state.directive, "info");
+        assert!(state.reverts_to.is_none());
+        assert!(state.reverts_at.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_second_temporary_log_filter_reverts_to_the_original_one() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+
+        log_filter
+            .set("debug", Some(Duration::from_secs(60)))
+            .unwrap();
+        log_filter
+            .set("trace", Some(Duration::from_secs(120)))
+            .unwrap();
+        // the first timeout no longer applies
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(log_filter.state().directive, "trace");
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert_eq!(log_filter.state().directive, "info");
+    }
+
+    #[tokio::test]
+    async fn a_permanent_log_filter_cancels_a_pending_revert() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+
+        log_filter
+            .set("debug", Some(Duration::from_secs(60)))
+            .unwrap();
+        log_filter.set("warn", None).unwrap();
+
+        let state = log_filter.state();
+        assert_eq!(state.directive, "warn");
+        assert!(state.reverts_to.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_revert_too_far_in_the_future_leaves_the_log_filter_unchanged() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+
+        let error = log_filter.set("debug", Some(Duration::MAX)).unwrap_err();
+
+        assert!(matches!(error, LogFilterError::RevertTooLate));
+        let state = log_filter.state();
+        assert_eq!(state.directive, "info");
+        assert!(state.reverts_to.is_none());
+    }
+
+    #[tokio::test]
+    async fn a_stale_revert_does_not_undo_a_later_change() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+        log_filter
+            .set("debug", Some(Duration::from_secs(60)))
+            .unwrap();
+        log_filter
+            .set("trace", Some(Duration::from_secs(120)))
+            .unwrap();
+
+        // the task of the first change was already running when the second one aborted it
+        log_filter.revert(0);
+
+        let state = log_filter.state();
+        assert_eq!(state.directive, "trace");
+        assert_eq!(state.reverts_to.as_deref(), Some("info"));
+    }
+
+    #[tokio::test]
+    async fn an_invalid_directive_leaves_the_log_filter_unchanged() {
+        let (_subscriber, log_filter) = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            RedactionPolicy::default(),
+            None,
+        );
+
+        let error = log_filter.set("zero2prod=loud", None).unwrap_err();
+
+        assert!(matches!(error, LogFilterError::InvalidDirective(_)));
+        assert_eq!(log_filter.state().directive, "info");
+    }
+}
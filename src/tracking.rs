@@ -0,0 +1,190 @@
+use ammonia::Url;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::newsletter_html::LinkRewriter;
+
+// tracking tokens carry the delivery they belong to, and the destination of click links.
+// they are signed, so the redirect endpoint cannot be abused to send readers anywhere
+const OPEN: u8 = b'o';
+const CLICK: u8 = b'c';
+
+// a transparent 1x1 GIF
+pub const TRACKING_PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+// the tracking enabled for a single email, identified by its delivery
+#[derive(Clone)]
+pub struct DeliveryTracking {
+    pub delivery_id: Uuid,
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    pub opens: bool,
+    pub clicks: bool,
+}
+
+impl DeliveryTracking {
+    pub fn open_pixel(&self) -> String {
+        let token = sign(&self.hmac_secret, OPEN, self.delivery_id.as_bytes());
+
+        format!(
+            r#"<img src="{}/t/o/{token}" width="1" height="1" alt="">"#,
+            self.base_url
+        )
+    }
+
+    // links back to the application, such as the unsubscribe link, are left untouched
+    pub fn link_rewriter(&self) -> LinkRewriter {
+        let tracking = self.clone();
+        let own_origin = Url::parse(&self.base_url).map(|url| url.origin()).ok();
+
+        Box::new(move |url: &Url| {
+            if Some(url.origin()) == own_origin {
+                return None;
+            }
+            let mut payload = tracking.delivery_id.as_bytes().to_vec();
+            payload.extend_from_slice(url.as_str().as_bytes());
+            let token = sign(&tracking.hmac_secret, CLICK, &payload);
+
+            Some(format!("{}/t/c/{token}", tracking.base_url))
+        })
+    }
+}
+
+pub fn verify_open_token(hmac_secret: &Secret<String>, token: &str) -> Option<Uuid> {
+    let payload = verify(hmac_secret, OPEN, token)?;
+
+    Uuid::from_slice(&payload).ok()
+}
+
+// returns the delivery and the destination of a click link
+pub fn verify_click_token(hmac_secret: &Secret<String>, token: &str) -> Option<(Uuid, String)> {
+    let payload = verify(hmac_secret, CLICK, token)?;
+    if payload.len() < 16 {
+        return None;
+    }
+    let (delivery_id, url) = payload.split_at(16);
+
+    Some((
+        Uuid::from_slice(delivery_id).ok()?,
+        String::from_utf8(url.to_vec()).ok()?,
+    ))
+}
+
+fn mac(hmac_secret: &Secret<String>, kind: u8, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hmac_secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size.");
+    // the kind is signed too, an open token cannot be replayed as a click token
+    mac.update(&[kind]);
+    mac.update(payload);
+    mac
+}
+
+fn sign(hmac_secret: &Secret<String>, kind: u8, payload: &[u8]) -> String {
+    let tag = mac(hmac_secret, kind, payload).finalize().into_bytes();
+
+    format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(payload),
+        URL_SAFE_NO_PAD.encode(tag)
+    )
+}
+
+fn verify(hmac_secret: &Secret<String>, kind: u8, token: &str) -> Option<Vec<u8>> {
+    let (payload, tag) = token.split_once('.')?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+    mac(hmac_secret, kind, &payload)
+        .verify_slice(&tag)
+        .ok()
+        .map(|_| payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_click_token, verify_open_token, DeliveryTracking};
+    use ammonia::Url;
+    use claims::{assert_none, assert_some_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn tracking() -> DeliveryTracking {
+        DeliveryTracking {
+            delivery_id: Uuid::new_v4(),
+            base_url: "https://newsletter.example.com".into(),
+            hmac_secret: Secret::new("secret".into()),
+            opens: true,
+            clicks: true,
+        }
+    }
+
+    // the token is the last path segment of a tracking url
+    fn token(url: &str) -> &str {
+        url.trim_end_matches(r#"" width="1" height="1" alt="">"#)
+            .rsplit('/')
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn click_tokens_carry_the_delivery_and_the_destination() {
+        let tracking = tracking();
+        let url = Url::parse("https://example.com/post?id=1").unwrap();
+
+        let rewritten = (tracking.link_rewriter())(&url).unwrap();
+
+        assert_some_eq!(
+            verify_click_token(&tracking.hmac_secret, token(&rewritten)),
+            (tracking.delivery_id, url.to_string())
+        );
+    }
+
+    #[test]
+    fn open_tokens_carry_the_delivery() {
+        let tracking = tracking();
+
+        let pixel = tracking.open_pixel();
+
+        assert_some_eq!(
+            verify_open_token(&tracking.hmac_secret, token(&pixel)),
+            tracking.delivery_id
+        );
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_rejected() {
+        let tracking = tracking();
+        let url = Url::parse("https://example.com").unwrap();
+        let rewritten = (tracking.link_rewriter())(&url).unwrap();
+
+        assert_none!(verify_click_token(
+            &Secret::new("another secret".into()),
+            token(&rewritten)
+        ));
+    }
+
+    #[test]
+    fn open_tokens_are_not_valid_click_tokens() {
+        let tracking = tracking();
+
+        let pixel = tracking.open_pixel();
+
+        assert_none!(verify_click_token(&tracking.hmac_secret, token(&pixel)));
+    }
+
+    #[test]
+    fn links_to_the_application_are_not_tracked() {
+        let tracking = tracking();
+        let url = Url::parse("https://newsletter.example.com/subscriptions/unsubscribe").unwrap();
+
+        assert_none!((tracking.link_rewriter())(&url));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn admin_requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/admin/issues", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn drafts_can_be_created_updated_and_deleted() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+
+    let issue: serde_json::Value = app.get_admin_issue(&issue_id).await.json().await.unwrap();
+    assert_eq!(issue["title"], "Draft title");
+    assert_eq!(issue["status"], "draft");
+
+    let response = app
+        .put_draft(
+            &issue_id,
+            serde_json::json!({
+                "title": "Updated title",
+                "content": {
+                    "text": "Updated body as plain text",
+                    "html": "<p>Updated body as HTML</p>",
+                }
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let issue: serde_json::Value = app.get_admin_issue(&issue_id).await.json().await.unwrap();
+    assert_eq!(issue["title"], "Updated title");
+    assert_eq!(issue["content"]["text"], "Updated body as plain text");
+
+    let response = app.delete_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.get_admin_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn drafts_are_listed_with_their_status() {
+    let app = spawn_app().await;
+    create_draft(&app).await;
+
+    let issues: serde_json::Value = app.get_admin_issues().await.json().await.unwrap();
+
+    assert_eq!(issues.as_array().unwrap().len(), 1);
+    assert_eq!(issues[0]["status"], "draft");
+}
+
+#[tokio::test]
+async fn drafts_are_not_delivered_until_published() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.publish_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited_or_deleted() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    app.publish_draft(&issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let response = app.put_draft(&issue_id, draft_request_body()).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app.delete_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app.publish_draft(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn drafts_with_unknown_merge_fields_cannot_be_published() {
+    let app = spawn_app().await;
+    // drafts are work in progress, placeholders are only checked when publishing
+    let response = app
+        .post_draft(serde_json::json!({
+            "title": "Draft for {{first_name}}",
+            "content": {
+                "text": "Draft body as plain text",
+                "html": "<p>Draft body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    let response = app.publish_draft(issue_id).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let issue: serde_json::Value = app.get_admin_issue(issue_id).await.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn the_preview_matches_the_email_sent_to_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let preview: serde_json::Value = app.get_issue_preview(&issue_id).await.json().await.unwrap();
+    app.publish_draft(&issue_id)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert!(!preview["html_body"].as_str().unwrap().contains("<script>"));
+    assert_eq!(body["Subject"], preview["subject"]);
+    assert_eq!(body["HtmlBody"], preview["html_body"]);
+    assert_eq!(body["TextBody"], preview["text_body"]);
+}
+
+#[tokio::test]
+async fn test_sends_are_delivered_to_the_logged_in_admin_only() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = create_draft(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_test_issue(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], app.test_user.email.as_str());
+
+    let issue: serde_json::Value = app.get_admin_issue(&issue_id).await.json().await.unwrap();
+    assert_eq!(issue["status"], "draft");
+}
+
+#[tokio::test]
+async fn unknown_issues_return_a_404() {
+    let app = spawn_app().await;
+    let issue_id = Uuid::new_v4().to_string();
+
+    assert_eq!(
+        app.get_issue_preview(&issue_id).await.status().as_u16(),
+        404
+    );
+    assert_eq!(app.post_test_issue(&issue_id).await.status().as_u16(), 404);
+    assert_eq!(app.publish_draft(&issue_id).await.status().as_u16(), 404);
+}
+
+fn draft_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft title",
+        "content": {
+            "text": "Draft body as plain text",
+            "html": "<style>p { color: red; }</style><p>Draft body as HTML</p><script>alert(1)</script>",
+        }
+    })
+}
+
+async fn create_draft(app: &TestApp) -> String {
+    let response = app.post_draft(draft_request_body()).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
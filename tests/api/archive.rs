@@ -0,0 +1,155 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn the_archive_lists_public_published_issues_only() {
+    let app = spawn_app().await;
+    publish_issue(&app, "Public issue", true).await;
+    publish_issue(&app, "Private issue", false).await;
+    app.post_draft(serde_json::json!({
+        "title": "Public draft",
+        "content": {"text": "Draft body", "html": "<p>Draft body</p>"},
+        "is_public": true,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = reqwest::get(format!("{}/archive", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+
+    assert!(html.contains("Public issue"));
+    assert!(!html.contains("Private issue"));
+    assert!(!html.contains("Public draft"));
+}
+
+#[tokio::test]
+async fn public_issues_can_be_viewed_in_the_browser() {
+    let app = spawn_app().await;
+    let slug = publish_issue(&app, "Public <issue>", true).await;
+
+    let response = reqwest::get(format!("{}/archive/{}", &app.address, slug))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+
+    assert!(html.contains("<h1>Public &lt;issue&gt;</h1>"));
+    assert!(html.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn private_issues_are_not_viewable() {
+    let app = spawn_app().await;
+    let slug = publish_issue(&app, "Private issue", false).await;
+
+    let response = reqwest::get(format!("{}/archive/{}", &app.address, slug))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_feed_contains_an_entry_per_public_issue() {
+    let app = spawn_app().await;
+    let slug = publish_issue(&app, "Public issue", true).await;
+    publish_issue(&app, "Private issue", false).await;
+
+    let response = reqwest::get(format!("{}/archive/feed.xml", &app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+
+    assert_eq!(feed.matches("<entry>").count(), 1);
+    assert!(feed.contains(&format!("/archive/{slug}</id>")));
+    assert!(feed.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn the_feed_only_carries_the_latest_issues() {
+    let app = spawn_app().await;
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id, title, text_content, html_content, published_at, slug, is_public
+        )
+        SELECT gen_random_uuid(), 'Issue ' || n, 'Body', '<p>Body</p>',
+            now() - n * interval '1 day', 'issue-' || n, true
+        FROM generate_series(1, 21) AS n
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let feed = reqwest::get(format!("{}/archive/feed.xml", &app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(feed.matches("<entry>").count(), 20);
+    assert!(feed.contains("<title>Issue 1</title>"));
+    assert!(!feed.contains("<title>Issue 21</title>"));
+}
+
+#[tokio::test]
+async fn public_issues_link_to_their_web_view() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let slug = publish_issue(&app, "Public issue", true).await;
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let web_view_url = format!("{}/archive/{}", app.base_url, slug);
+
+    assert!(body["HtmlBody"].as_str().unwrap().contains(&web_view_url));
+    assert!(body["TextBody"].as_str().unwrap().contains(&web_view_url));
+}
+
+// publish an issue right away and return its slug
+async fn publish_issue(app: &TestApp, title: &str, is_public: bool) -> String {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "is_public": is_public,
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    let issue: serde_json::Value = app.get_admin_issue(issue_id).await.json().await.unwrap();
+    issue["slug"].as_str().unwrap().to_owned()
+}
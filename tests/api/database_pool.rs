@@ -0,0 +1,190 @@
+use sqlx::{Connection, Executor, PgConnection};
+use std::net::TcpListener;
+use std::time::Duration;
+
+use crate::helpers::{spawn_app_with, TestApp};
+use zero2prod_newsletter::configuration::{ReadReplicaSettings, Settings};
+
+// spawns an application and opens a connection outside of its pools
+async fn spawn_app_and_connect(configure: impl FnOnce(&mut Settings)) -> (TestApp, PgConnection) {
+    let mut configuration = None;
+    let app = spawn_app_with(|c| {
+        configure(c);
+        configuration = Some(c.clone());
+    })
+    .await;
+    let connection = PgConnection::connect_with(&configuration.unwrap().database.with_db())
+        .await
+        .expect("Failed to connect to Postgres.");
+
+    (app, connection)
+}
+
+async fn lock_subscriptions(connection: &mut PgConnection) {
+    connection.execute("BEGIN").await.unwrap();
+    connection
+        .execute("LOCK TABLE subscriptions IN ACCESS EXCLUSIVE MODE")
+        .await
+        .unwrap();
+}
+
+async fn get_unsubscribe(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token=unknown",
+            &app.address
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn connections_are_configured_from_the_database_settings() {
+    let app = spawn_app_with(|c| {
+        c.database.application_name = "zero2prod-test".into();
+        c.database.statement_timeout_milliseconds = 1234;
+    })
+    .await;
+
+    let (application_name, statement_timeout): (String, String) = sqlx::query_as(
+        "SELECT current_setting('application_name'), current_setting('statement_timeout')",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert_eq!(application_name, "zero2prod-test");
+    assert_eq!(statement_timeout, "1234ms");
+}
+
+#[tokio::test]
+async fn a_statement_exceeding_the_timeout_returns_a_503() {
+    let (app, mut connection) =
+        spawn_app_and_connect(|c| c.database.statement_timeout_milliseconds = 200).await;
+    lock_subscriptions(&mut connection).await;
+
+    let response = tokio::time::timeout(Duration::from_secs(5), get_unsubscribe(&app))
+        .await
+        .expect("The request hung.");
+
+    assert_eq!(response.status().as_u16(), 503);
+}
+
+#[tokio::test]
+async fn an_exhausted_pool_returns_a_503_after_the_acquire_timeout() {
+    let (app, mut connection) = spawn_app_and_connect(|c| {
+        c.database.max_connections = 1;
+        c.database.acquire_timeout_milliseconds = 200;
+    })
+    .await;
+    lock_subscriptions(&mut connection).await;
+
+    // the first request holds the only connection while it waits on the lock
+    let address = app.address.clone();
+    let blocked = tokio::spawn(async move {
+        reqwest::get(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token=unknown",
+            address
+        ))
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let response = tokio::time::timeout(Duration::from_secs(5), get_unsubscribe(&app))
+        .await
+        .expect("The request hung.");
+
+    assert_eq!(response.status().as_u16(), 503);
+    connection.execute("ROLLBACK").await.unwrap();
+    assert_eq!(blocked.await.unwrap().unwrap().status().as_u16(), 401);
+}
+
+async fn get_archive(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/archive", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn read_only_queries_are_served_by_the_read_replica() {
+    // the primary stands in for the replica, the connections of the read pool are told apart
+    // by their application name
+    let app = spawn_app_with(|c| {
+        c.database.application_name = "zero2prod-test".into();
+        c.database.read_replica = Some(ReadReplicaSettings {
+            host: c.database.host.clone(),
+            port: c.database.port,
+        });
+    })
+    .await;
+
+    let response = get_archive(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let queries: Vec<(String, String)> = sqlx::query_as(
+        "SELECT application_name, query FROM pg_stat_activity \
+        WHERE datname = current_database() AND pid <> pg_backend_pid() \
+        AND query LIKE '%FROM newsletter_issues%'",
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(!queries.is_empty());
+    for (application_name, _) in queries {
+        assert_eq!(application_name, "zero2prod-test-replica");
+    }
+}
+
+#[tokio::test]
+async fn health_ready_reports_the_read_replica_without_depending_on_it() {
+    let unused_port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let app = spawn_app_with(|c| {
+        c.database.acquire_timeout_milliseconds = 500;
+        c.database.read_replica = Some(ReadReplicaSettings {
+            host: "127.0.0.1".into(),
+            port: unused_port,
+        });
+    })
+    .await;
+
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["read_pool"]["status"], "down");
+}
+
+#[tokio::test]
+async fn writes_stay_on_the_primary_when_the_read_replica_is_down() {
+    // nothing listens on a port once its listener is dropped
+    let unused_port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let app = spawn_app_with(|c| {
+        c.database.acquire_timeout_milliseconds = 500;
+        c.database.read_replica = Some(ReadReplicaSettings {
+            host: "127.0.0.1".into(),
+            port: unused_port,
+        });
+    })
+    .await;
+
+    let response = get_archive(&app).await;
+    assert_eq!(response.status().as_u16(), 503);
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
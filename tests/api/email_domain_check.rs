@@ -0,0 +1,162 @@
+use hickory_proto::op::{Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::{A, MX};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+// a DNS server answering from a fixed set of records, unknown domains do not exist
+async fn spawn_dns_stub(records: Vec<(&str, RData)>) -> SocketAddr {
+    let records: HashMap<(Name, RecordType), RData> = records
+        .into_iter()
+        .map(|(name, rdata)| {
+            (
+                (Name::from_ascii(name).unwrap(), rdata.record_type()),
+                rdata,
+            )
+        })
+        .collect();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0; 512];
+        while let Ok((len, peer)) = socket.recv_from(&mut buffer).await {
+            let request = Message::from_vec(&buffer[..len]).unwrap();
+            let mut response = Message::new();
+            response
+                .set_id(request.id())
+                .set_message_type(MessageType::Response)
+                .set_recursion_available(true);
+            for query in request.queries() {
+                response.add_query(query.clone());
+                let known_domain = records.keys().any(|(name, _)| name == query.name());
+                match records.get(&(query.name().clone(), query.query_type())) {
+                    Some(rdata) => {
+                        response.add_answer(Record::from_rdata(
+                            query.name().clone(),
+                            60,
+                            rdata.clone(),
+                        ));
+                    }
+                    None if !known_domain => {
+                        response.set_response_code(ResponseCode::NXDomain);
+                    }
+                    None => {}
+                }
+            }
+            socket
+                .send_to(&response.to_vec().unwrap(), peer)
+                .await
+                .unwrap();
+        }
+    });
+
+    address
+}
+
+fn mx(exchange: &str) -> RData {
+    RData::MX(MX::new(10, Name::from_ascii(exchange).unwrap()))
+}
+
+async fn spawn_app_with_dns(records: Vec<(&str, RData)>) -> TestApp {
+    let resolver_address = spawn_dns_stub(records).await;
+    let app = spawn_app_with(|c| {
+        c.email_domain_check.enabled = true;
+        c.email_domain_check.resolver_address = resolver_address;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app
+}
+
+#[tokio::test]
+async fn subscribe_accepts_domains_with_an_mx_record() {
+    let app = spawn_app_with_dns(vec![("gmail.com.", mx("mx.gmail.com."))]).await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_domains_falling_back_to_an_a_record() {
+    let app = spawn_app_with_dns(vec![(
+        "example.com.",
+        RData::A(A(Ipv4Addr::new(93, 184, 216, 34))),
+    )])
+    .await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40example.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_domains_publishing_a_null_mx() {
+    let app = spawn_app_with_dns(vec![("example.com.", mx("."))]).await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40example.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_suggests_a_correction_for_misspelled_providers() {
+    let app = spawn_app_with_dns(vec![("gmail.com.", mx("mx.gmail.com."))]).await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmial.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    let message = response.text().await.unwrap();
+    assert!(message.contains("Did you mean ursula_le_guin@gmail.com?"));
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn subscribe_lets_addresses_through_when_the_resolver_is_unreachable() {
+    // nothing listens on the port of a socket that was just closed
+    let resolver_address = UdpSocket::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let app = spawn_app_with(|c| {
+        c.email_domain_check.enabled = true;
+        c.email_domain_check.resolver_address = resolver_address;
+        c.email_domain_check.timeout_milliseconds = 200;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
@@ -1,16 +1,161 @@
-use crate::helpers::spawn_app;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
 
-#[tokio::test]
-async fn health_check_works() {
-    let app = spawn_app().await;
-    let client = reqwest::Client::new();
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use sqlx::{Connection, Executor, PgConnection};
+use zero2prod_newsletter::configuration::get_configuration;
 
-    let response = client
+#[allow(clippy::needless_borrows_for_generic_args)]
+async fn get_health_check(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
         .get(&format!("{}/health_check", &app.address))
         .send()
         .await
-        .expect("Failed to execute request.");
+        .expect("Failed to execute request.")
+}
+
+async fn get_health_ready(app: &TestApp) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/health/ready", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn health_check_works() {
+    let app = spawn_app().await;
+
+    let response = get_health_check(&app).await;
 
     assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email_provider"]["circuit_breaker"], "closed");
+}
+
+#[tokio::test]
+async fn health_check_reports_an_open_circuit_breaker_when_the_email_provider_is_down() {
+    let app = spawn_app_with(|c| c.email_client.circuit_breaker.failure_threshold = 1).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // the first confirmation email trips the breaker, the second one stays in the outbox
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=tolkien&email=jrr_tolkien%40gmail.com",
+    ] {
+        let response = app.post_subscription(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.dispatch_outbox().await;
+    let response = get_health_check(&app).await;
+
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["email_provider"]["circuit_breaker"], "open");
+}
+
+#[tokio::test]
+async fn health_ready_reports_every_dependency_when_they_are_up() {
+    let app = spawn_app().await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let response = get_health_ready(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["read_pool"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "up");
+    assert_eq!(body["checks"]["email_provider"]["status"], "up");
+    assert_eq!(body["checks"]["queue_backlog"]["issue_deliveries"], 0);
+    assert_eq!(body["checks"]["queue_backlog"]["outbox_messages"], 1);
+}
+
+#[tokio::test]
+async fn health_ready_does_not_call_the_email_provider() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for _ in 0..3 {
+        let response = get_health_ready(&app).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn health_ready_returns_a_503_when_migrations_are_pending() {
+    let app = spawn_app().await;
+    app.db_pool
+        .execute("DELETE FROM _sqlx_migrations WHERE version = (SELECT max(version) FROM _sqlx_migrations)")
+        .await
+        .unwrap();
+
+    let response = get_health_ready(&app).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "not_ready");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert_eq!(
+        body["checks"]["migrations"]["pending"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn health_ready_returns_a_503_when_the_database_is_unreachable() {
+    let app = spawn_app().await;
+    let database_name: String = sqlx::query_scalar("SELECT current_database()")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let configuration = get_configuration().unwrap();
+    let mut connection = PgConnection::connect_with(&configuration.database.without_db())
+        .await
+        .unwrap();
+    // terminates the connections the application holds as well
+    connection
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, database_name).as_str())
+        .await
+        .unwrap();
+
+    let response = get_health_ready(&app).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "down");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+}
+
+#[tokio::test]
+async fn health_ready_stays_ready_while_the_email_provider_is_down() {
+    let app = spawn_app_with(|c| c.email_client.circuit_breaker.failure_threshold = 1).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox().await;
+
+    let response = get_health_ready(&app).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["status"], "down");
+    assert_eq!(body["checks"]["email_provider"]["circuit_breaker"], "open");
 }
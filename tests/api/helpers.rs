@@ -1,23 +1,56 @@
+use fake::faker::internet::en::SafeEmail;
+use fake::Fake;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use std::{env, io};
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod_newsletter::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod_newsletter::authentication::compute_password_hash;
+use zero2prod_newsletter::configuration::{
+    get_configuration, DatabaseSettings, MetricsSettings, Settings, TrackingSettings,
+    WebhookSettings,
+};
+use zero2prod_newsletter::email_client::EmailClient;
+use zero2prod_newsletter::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod_newsletter::issue_scheduler::{try_enqueue_due_issue, SchedulerOutcome};
+use zero2prod_newsletter::outbox_dispatcher::{try_dispatch_message, DispatchOutcome};
 use zero2prod_newsletter::startup::{get_connection_pool, Application};
-use zero2prod_newsletter::telemetry::{get_subscriber, init_subscriber};
+use zero2prod_newsletter::telemetry::{get_subscriber, init_subscriber, LogFilter};
 
 // Ensure that the 'tracing stack is only initialised once using 'once_cell'
-static TRACING: Lazy<()> = Lazy::new(|| {
+// every application under test shares the filter of the global subscriber
+static TRACING: Lazy<LogFilter> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    // the logs of the tests are redacted as the application's would be
+    let redaction = get_configuration()
+        .expect("Failed to read configuration.")
+        .redaction
+        .policy();
 
     if env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, io::stdout);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            io::stdout,
+            redaction,
+            None,
+        );
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, io::sink);
+        let (subscriber, log_filter) = get_subscriber(
+            subscriber_name,
+            default_filter_level,
+            io::sink,
+            redaction,
+            None,
+        );
         init_subscriber(subscriber);
+        log_filter
     }
 });
 
@@ -26,6 +59,47 @@ pub struct TestApp {
     pub port: u16,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub email_client: EmailClient,
+    pub base_url: String,
+    pub tracking: TrackingSettings,
+    pub webhooks: WebhookSettings,
+    pub metrics_scraper: MetricsSettings,
+    pub test_user: TestUser,
+    pub api_client: reqwest::Client,
+    // cancelling it shuts the application down
+    pub shutdown: CancellationToken,
+}
+
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+    pub email: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+            email: SafeEmail().fake(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        let password_hash = compute_password_hash(Secret::new(self.password.clone())).unwrap();
+        sqlx::query!(
+            "INSERT INTO users (user_id, username, password_hash, email) VALUES ($1, $2, $3, $4)",
+            self.user_id,
+            self.username,
+            password_hash.expose_secret(),
+            self.email,
+        )
+        .execute(pool)
+        .await
+        .expect("Failed to store test user.");
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -34,6 +108,7 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    #[allow(clippy::needless_borrows_for_generic_args)]
     pub async fn post_subscription(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
             .post(&format!("{}/subscriptions", &self.address))
@@ -44,6 +119,233 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    // every request to a protected endpoint carries the test user credentials
+    pub fn authenticated(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.api_client
+            .request(method, format!("{}{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.authenticated(reqwest::Method::POST, "/newsletters")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_newsletter_schedule(
+        &self,
+        newsletter_issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::PUT,
+            &format!("/newsletters/{newsletter_issue_id}/schedule"),
+        )
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_newsletter_schedule(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::DELETE,
+            &format!("/newsletters/{newsletter_issue_id}/schedule"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn post_draft(&self, body: serde_json::Value) -> reqwest::Response {
+        self.authenticated(reqwest::Method::POST, "/admin/issues")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issues(&self) -> reqwest::Response {
+        self.authenticated(reqwest::Method::GET, "/admin/issues")
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_admin_issue(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::GET,
+            &format!("/admin/issues/{newsletter_issue_id}"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn put_draft(
+        &self,
+        newsletter_issue_id: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::PUT,
+            &format!("/admin/issues/{newsletter_issue_id}"),
+        )
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_draft(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::DELETE,
+            &format!("/admin/issues/{newsletter_issue_id}"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn publish_draft(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::POST,
+            &format!("/admin/issues/{newsletter_issue_id}/publish"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_preview(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::GET,
+            &format!("/admin/issues/{newsletter_issue_id}/preview"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_report(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::GET,
+            &format!("/admin/issues/{newsletter_issue_id}/report"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn post_test_issue(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::POST,
+            &format!("/admin/issues/{newsletter_issue_id}/test"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    // authenticates with the webhook credentials, as Postmark does
+    pub async fn post_postmark_webhook(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.webhooks.username,
+                Some(self.webhooks.password.expose_secret()),
+            )
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/metrics", &self.address))
+            .basic_auth(
+                &self.metrics_scraper.username,
+                Some(self.metrics_scraper.password.expose_secret()),
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_suppression(&self, body: serde_json::Value) -> reqwest::Response {
+        self.authenticated(reqwest::Method::POST, "/admin/suppressions")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions(&self) -> reqwest::Response {
+        self.authenticated(reqwest::Method::GET, "/admin/suppressions")
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_log_filter(&self) -> reqwest::Response {
+        self.authenticated(reqwest::Method::GET, "/admin/log_filter")
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_log_filter(&self, body: serde_json::Value) -> reqwest::Response {
+        self.authenticated(reqwest::Method::PUT, "/admin/log_filter")
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_suppression(&self, entry_id: &str) -> reqwest::Response {
+        self.authenticated(
+            reqwest::Method::DELETE,
+            &format!("/admin/suppressions/{entry_id}"),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+    }
+
+    pub async fn dispatch_all_pending_emails(&self) {
+        // stops on an empty queue, or when the quota leaves tasks in the queue
+        while let ExecutionOutcome::TaskCompleted = try_execute_task(
+            &self.db_pool,
+            &self.email_client,
+            &self.base_url,
+            &self.tracking,
+        )
+        .await
+        .unwrap()
+        {}
+    }
+
+    pub async fn dispatch_outbox(&self) {
+        while let DispatchOutcome::MessageProcessed =
+            try_dispatch_message(&self.db_pool, &self.email_client)
+                .await
+                .unwrap()
+        {}
+    }
+
+    pub async fn enqueue_due_issues(&self) {
+        loop {
+            if let SchedulerOutcome::NoDueIssue =
+                try_enqueue_due_issue(&self.db_pool).await.unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     // extract the confirmation links embedded in the request to the email API
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
@@ -64,7 +366,9 @@ impl TestApp {
             confirmation_link
         };
 
+        #[allow(clippy::needless_borrow)]
         let html = get_link(&body["HtmlBody"].as_str().unwrap());
+        #[allow(clippy::needless_borrow)]
         let plain_text = get_link(&body["TextBody"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
@@ -72,8 +376,13 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// lets a test adjust the configuration before the application is built
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // on the first time, the code inside 'TRACING' is executed. All other times, will be skipped
-    Lazy::force(&TRACING);
+    let log_filter = Lazy::force(&TRACING).clone();
 
     // Launch a mock server to stand in for Postmark's API
     let email_server = MockServer::start().await;
@@ -84,24 +393,49 @@ pub async fn spawn_app() -> TestApp {
         c.database.database_name = Uuid::new_v4().to_string(); // different database for each test case
         c.application.port = 0; // random OS port
         c.email_client.base_url = email_server.uri();
+        // the database is migrated below, unless a test wants the application to do it
+        c.database.run_migrations_on_startup = false;
+        configure(&mut c);
         c
     };
 
     // create and migrate the database
     configure_database(&configuration.database).await;
 
-    let application = Application::build(configuration.clone())
-        .await
-        .expect("Failed to build application.");
+    let email_client = configuration
+        .email_client
+        .clone()
+        .client(get_connection_pool(&configuration.database));
+    let shutdown = CancellationToken::new();
+    let application = Application::build(
+        configuration.clone(),
+        email_client.clone(),
+        log_filter,
+        shutdown.clone(),
+    )
+    .await
+    .expect("Failed to build application.");
     let application_port = application.port();
+    #[allow(clippy::let_underscore_future)]
     let _ = tokio::spawn(application.run_until_stopped());
 
-    TestApp {
+    let test_app = TestApp {
         address: format!("http://localhost:{}", application_port),
         port: application_port,
         db_pool: get_connection_pool(&configuration.database),
         email_server,
-    }
+        email_client,
+        base_url: configuration.application.base_url,
+        tracking: configuration.tracking,
+        webhooks: configuration.webhooks,
+        metrics_scraper: configuration.metrics,
+        test_user: TestUser::generate(),
+        api_client: reqwest::Client::new(),
+        shutdown,
+    };
+    test_app.test_user.store(&test_app.db_pool).await;
+
+    test_app
 }
 
 async fn configure_database(config: &DatabaseSettings) -> PgPool {
@@ -120,10 +454,51 @@ async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to connect to Postgres.");
 
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
-        .await
-        .expect("Failed to migrate the database.");
+    if !config.run_migrations_on_startup {
+        sqlx::migrate!("./migrations")
+            .run(&connection_pool)
+            .await
+            .expect("Failed to migrate the database.");
+    }
 
     connection_pool
 }
+
+// use the public API of the application under the test to create an unconfirmed subscriber
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_outbox().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    let confirmation_link = create_unconfirmed_subscriber(app).await;
+
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
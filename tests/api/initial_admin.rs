@@ -0,0 +1,58 @@
+use secrecy::Secret;
+use uuid::Uuid;
+use zero2prod_newsletter::authentication::{compute_password_hash, create_initial_admin};
+use zero2prod_newsletter::configuration::InitialAdminSettings;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn get_admin_issues_as(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/issues", &app.address))
+        .basic_auth(username, Some(password))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn initial_admin(username: &str, password: &str) -> InitialAdminSettings {
+    InitialAdminSettings {
+        username: username.into(),
+        email: "admin@example.com".into(),
+        password_hash: Some(compute_password_hash(Secret::new(password.into())).unwrap()),
+    }
+}
+
+#[tokio::test]
+async fn there_is_no_admin_until_one_is_configured() {
+    let app = spawn_app().await;
+
+    let response = get_admin_issues_as(&app, "admin", "everythinghastostartsomewhere").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_initial_admin_is_created_from_the_configuration() {
+    let password = Uuid::new_v4().to_string();
+    let settings = initial_admin("admin", &password);
+    let app = spawn_app_with(|c| c.initial_admin = settings).await;
+
+    let response = get_admin_issues_as(&app, "admin", &password).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn no_initial_admin_is_created_once_there_is_a_user() {
+    let app = spawn_app().await;
+
+    create_initial_admin(&app.db_pool, &initial_admin("second-admin", "password"))
+        .await
+        .unwrap();
+
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM users")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 1);
+}
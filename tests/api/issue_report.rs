@@ -0,0 +1,174 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
+
+#[tokio::test]
+async fn the_report_counts_deliveries_and_engagement() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    mount_postmark(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["queued"], 1);
+    assert_eq!(report["sent"], 0);
+
+    app.dispatch_all_pending_emails().await;
+    let delivery = sqlx::query!("SELECT delivery_id, message_id FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let delivery_id = delivery.delivery_id;
+    for kind in ["open", "open", "click"] {
+        sqlx::query!(
+            "INSERT INTO tracking_events (event_id, delivery_id, kind, occurred_at) \
+            VALUES ($1, $2, $3, now())",
+            Uuid::new_v4(),
+            delivery_id,
+            kind
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    post_bounce(&app, 1, "HardBounce", delivery.message_id.as_deref()).await;
+
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["queued"], 0);
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 0);
+    assert_eq!(report["bounced"], 1);
+    assert_eq!(report["opened"], 1);
+    assert_eq!(report["clicked"], 1);
+    assert_eq!(report["unsubscribed_after_issue"], 1);
+    let sends_per_minute = report["sends_per_minute"].as_array().unwrap();
+    assert_eq!(sends_per_minute.len(), 1);
+    assert_eq!(sends_per_minute[0]["sent"], 1);
+}
+
+#[tokio::test]
+async fn only_hard_bounces_of_the_issue_are_counted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    mount_postmark(&app).await;
+    let issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    let message_id = sqlx::query!("SELECT message_id FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .message_id;
+
+    post_bounce(&app, 1, "SoftBounce", message_id.as_deref()).await;
+    // a later email to the same address bounced, not this issue
+    post_bounce(&app, 2, "HardBounce", Some("another-message")).await;
+
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["bounced"], 0);
+}
+
+#[tokio::test]
+async fn an_unsubscription_is_attributed_to_the_last_issue_received() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    mount_postmark(&app).await;
+    let first_issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    let second_issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let report = get_report(&app, &first_issue_id).await;
+    assert_eq!(report["unsubscribed_after_issue"], 0);
+    let report = get_report(&app, &second_issue_id).await;
+    assert_eq!(report["unsubscribed_after_issue"], 1);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_reported() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app).await;
+
+    app.dispatch_all_pending_emails().await;
+
+    let report = get_report(&app, &issue_id).await;
+    assert_eq!(report["sent"], 0);
+    assert_eq!(report["failed"], 1);
+    assert!(report["sends_per_minute"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reporting_on_an_unknown_issue_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.get_issue_report(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+// every accepted email gets its own id, as Postmark does
+async fn mount_postmark(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(|_: &Request| {
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "MessageID": Uuid::new_v4() }))
+        })
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn post_bounce(app: &TestApp, id: i64, bounce_type: &str, message_id: Option<&str>) {
+    app.post_postmark_webhook(serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": bounce_type,
+        "Email": "ursula_le_guin@gmail.com",
+        "MessageID": message_id,
+        "BouncedAt": Utc::now(),
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+}
+
+async fn publish_issue(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+async fn get_report(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    let response = app.get_issue_report(issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    response.json().await.unwrap()
+}
@@ -0,0 +1,73 @@
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn the_log_filter_cannot_be_changed_without_authorization() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .put(format!("{}/admin/log_filter", &app.address))
+        .json(&serde_json::json!({ "directive": "trace" }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_directives_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "directive": "zero2prod=loud" }),
+            "an unknown level",
+        ),
+        (
+            serde_json::json!({ "directive": "debug", "revert_after_seconds": 0 }),
+            "a revert without delay",
+        ),
+        (
+            serde_json::json!({ "directive": "debug", "revert_after_seconds": u64::MAX }),
+            "a revert too far in the future",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.put_log_filter(body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+    // the log filter is still usable
+    assert_eq!(200, app.get_log_filter().await.status().as_u16());
+}
+
+// every application under test shares the global subscriber, this is the only test changing it
+#[tokio::test]
+async fn the_log_filter_can_be_changed_temporarily() {
+    let app = spawn_app().await;
+    let initial: serde_json::Value = app.get_log_filter().await.json().await.unwrap();
+
+    let response = app
+        .put_log_filter(serde_json::json!({
+            "directive": "zero2prod_newsletter=debug",
+            "revert_after_seconds": 1,
+        }))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let state: serde_json::Value = app.get_log_filter().await.json().await.unwrap();
+    assert_eq!(state["directive"], "zero2prod_newsletter=debug");
+    assert_eq!(state["reverts_to"], initial["directive"]);
+    assert!(state["reverts_at"].is_string());
+
+    tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+    let state: serde_json::Value = app.get_log_filter().await.json().await.unwrap();
+    assert_eq!(state["directive"], initial["directive"]);
+    assert!(state["reverts_to"].is_null());
+}
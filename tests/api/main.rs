@@ -1,5 +1,23 @@
+mod admin_issues;
+mod archive;
+mod database_pool;
+mod email_domain_check;
 mod health_check;
 mod helpers;
+mod initial_admin;
+mod issue_report;
+mod log_filter;
+mod metrics;
+mod migrations;
 mod newsletter;
+mod outbound_rate_limit;
+mod outbox;
+mod rate_limiting;
+mod shutdown;
 mod subscriptions;
+mod subscriptions_challenge;
 mod subscriptions_confirm;
+mod subscriptions_unsubscribe;
+mod suppressions;
+mod tracking;
+mod webhooks;
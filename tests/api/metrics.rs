@@ -0,0 +1,111 @@
+use crate::helpers::{create_unconfirmed_subscriber, spawn_app, TestApp};
+
+async fn get_metrics(app: &TestApp) -> String {
+    let response = app.get_metrics().await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+
+    response.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn metrics_are_exported_in_the_prometheus_text_format() {
+    let app = spawn_app().await;
+    // a request is only counted once it has been served
+    reqwest::get(format!("{}/health_check", &app.address))
+        .await
+        .unwrap();
+
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains("# TYPE zero2prod_http_requests_total counter"));
+    assert!(metrics.contains("# TYPE zero2prod_http_request_duration_seconds histogram"));
+    assert!(metrics.contains(r#"zero2prod_queue_depth{queue="email_outbox"} 0"#));
+    assert!(metrics.contains(r#"zero2prod_db_pool_connections{state="idle"}"#));
+}
+
+#[tokio::test]
+async fn the_subscription_funnel_and_the_emails_sent_are_counted() {
+    let app = spawn_app().await;
+
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+    // following the link twice confirms the subscriber only once
+    for _ in 0..2 {
+        reqwest::get(confirmation_links.html.clone())
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(r#"zero2prod_subscriptions_total{stage="created"} 1"#));
+    assert!(metrics.contains(r#"zero2prod_subscriptions_total{stage="confirmed"} 1"#));
+    assert!(metrics.contains(r#"zero2prod_emails_sent_total{kind="transactional"} 1"#));
+}
+
+#[tokio::test]
+async fn requests_are_labelled_with_their_route_pattern() {
+    let app = spawn_app().await;
+
+    reqwest::get(format!("{}/archive/some-slug", &app.address))
+        .await
+        .unwrap();
+    reqwest::get(format!("{}/not-a-route", &app.address))
+        .await
+        .unwrap();
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="/archive/{slug}",status="404"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="unmatched",status="404"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn requests_rejected_by_a_middleware_are_counted() {
+    let app = spawn_app().await;
+
+    reqwest::get(format!("{}/admin/issues", &app.address))
+        .await
+        .unwrap();
+    let metrics = get_metrics(&app).await;
+
+    assert!(metrics.contains(
+        r#"zero2prod_http_requests_total{method="GET",route="/admin/issues",status="401"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn metrics_are_not_public() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(
+        response.headers()["WWW-Authenticate"],
+        r#"Basic realm="metrics""#
+    );
+}
+
+#[tokio::test]
+async fn admin_credentials_do_not_grant_access_to_the_metrics() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/metrics", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
@@ -0,0 +1,25 @@
+use crate::helpers::spawn_app_with;
+use zero2prod_newsletter::configuration::Settings;
+use zero2prod_newsletter::migrations::{pending_migrations, run_migrations};
+
+#[tokio::test]
+async fn the_application_migrates_its_database_on_startup_when_configured() {
+    // the helpers leave the database empty for the application to migrate
+    let app = spawn_app_with(|c| c.database.run_migrations_on_startup = true).await;
+
+    let pending = pending_migrations(&app.db_pool).await.unwrap();
+
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn running_migrations_on_an_up_to_date_database_applies_nothing() {
+    let mut configuration: Option<Settings> = None;
+    let _app = spawn_app_with(|c| configuration = Some(c.clone())).await;
+
+    let applied = run_migrations(&configuration.unwrap().database)
+        .await
+        .unwrap();
+
+    assert!(applied.is_empty());
+}
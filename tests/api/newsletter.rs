@@ -1,6 +1,13 @@
-use crate::helpers::{spawn_app, TestApp};
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, spawn_app_with, TestApp,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod_newsletter::domain::SubscriberEmail;
+use zero2prod_newsletter::email_client::EmailKind;
+use zero2prod_newsletter::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -21,30 +28,573 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
         }
     });
 
-    let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
-        .json(&newsletter_request_body)
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn deliveries_pause_once_the_daily_cap_is_reached() {
+    // the confirmation email uses up the whole quota
+    let app = spawn_app_with(|c| c.email_client.daily_cap = Some(1)).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.tracking,
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        outcome,
+        ExecutionOutcome::QuotaExhausted { resumes_at } if resumes_at > Utc::now()
+    ));
+    let queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+    assert_eq!(app.email_client.throttle_metrics().daily_cap_rejections, 1);
+}
+
+#[tokio::test]
+async fn deliveries_stay_queued_while_the_email_provider_is_down() {
+    let app = spawn_app_with(|c| c.email_client.circuit_breaker.failure_threshold = 1).await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // a failed email opens the circuit breaker, shared with the application
+    let recipient = SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap();
+    app.email_client
+        .send_email(
+            EmailKind::Newsletter,
+            recipient,
+            "Subject",
+            "<p>Body</p>",
+            "Body",
+        )
+        .await
+        .unwrap_err();
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let outcome = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.base_url,
+        &app.tracking,
+    )
+    .await
+    .unwrap();
+
+    assert!(matches!(outcome, ExecutionOutcome::ProviderUnavailable));
+    let queued = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.count, 1);
+}
+
+#[tokio::test]
+async fn newsletters_html_is_sanitized_before_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<style>p { color: red; }</style>\
+                <p>Newsletter body as HTML</p>\
+                <script>alert('xss')</script>\
+                <a href=\"/archive\">Archive</a>",
+        }
+    });
+
+    app.post_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+
+    assert!(!html.contains("<script>"));
+    assert!(html.contains(r#"<p style="color: red;">Newsletter body as HTML</p>"#));
+    assert!(html.contains(r#"href="http://127.0.0.1/archive""#));
+}
+
+#[tokio::test]
+async fn merge_fields_are_resolved_for_each_recipient() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "News for {{name}}",
+        "content": {
+            "text": "Hi {{name|there}}, unsubscribe at {{unsubscribe_url}}",
+            "html": "<p>Hi {{name|there}}, reader since {{subscribed_since}}</p>\
+                <a href=\"{{unsubscribe_url}}\">Unsubscribe</a>",
+        }
+    });
+
+    app.post_newsletters(newsletter_request_body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    let text = body["TextBody"].as_str().unwrap();
+    let unsubscribe_url = format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=",
+        app.base_url
+    );
+    let subscribed_since = Utc::now().format("%B %-d, %Y").to_string();
+
+    assert_eq!(body["Subject"].as_str().unwrap(), "News for le guin");
+    assert!(text.starts_with(&format!("Hi le guin, unsubscribe at {unsubscribe_url}")));
+    assert!(html.contains(&format!("Hi le guin, reader since {subscribed_since}")));
+    assert!(html.contains(&unsubscribe_url));
+    assert!(!html.contains("{{"));
+}
+
+#[tokio::test]
+async fn newsletters_offer_one_click_unsubscription_to_mail_clients() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let headers = body["Headers"].as_array().unwrap();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|header| header["Name"] == name)
+            .and_then(|header| header["Value"].as_str())
+            .unwrap()
+            .to_owned()
+    };
+    assert!(header("List-Unsubscribe").starts_with(&format!(
+        "<{}/subscriptions/unsubscribe?unsubscribe_token=",
+        app.base_url
+    )));
+    assert_eq!(
+        header("List-Unsubscribe-Post"),
+        "List-Unsubscribe=One-Click"
+    );
+}
+
+#[tokio::test]
+async fn newsletters_with_unknown_merge_fields_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Hi {{first_name|there}}",
+            "html": "<p>Hi {{first_name|there}}</p>",
+        }
+    });
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 400);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .json(&newsletter_request_body(None))
         .send()
         .await
         .expect("Failed to execute request.");
 
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    let app = spawn_app().await;
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&newsletter_request_body(None))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    let app = spawn_app().await;
+    let password = Uuid::new_v4().to_string();
+    assert_ne!(app.test_user.password, password);
+
+    let response = app
+        .api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&app.test_user.username, Some(password))
+        .json(&newsletter_request_body(None))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn scheduled_newsletters_are_not_delivered_before_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(newsletter_request_body(Some(
+            Utc::now() + Duration::hours(1),
+        )))
+        .await;
     assert_eq!(response.status().as_u16(), 200);
+
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
 }
 
-// use the public API of the application under the test to create an unconfirmed subscriber
-async fn create_unconfirmed_subscriber(app: &TestApp) {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn scheduled_newsletters_are_delivered_once_they_are_due() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    let _mock_guard = Mock::given(path("/email"))
+    Mock::given(path("/email"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscription(body.into())
+    let issue_id = post_scheduled_newsletter(&app).await;
+    make_issue_due(&app, &issue_id).await;
+
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+    // a second pass of the scheduler must not enqueue the issue again
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_enqueue_a_due_issue_exactly_once() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let issue_id = post_scheduled_newsletter(&app).await;
+    make_issue_due(&app, &issue_id).await;
+
+    tokio::join!(
+        app.enqueue_due_issues(),
+        app.enqueue_due_issues(),
+        app.enqueue_due_issues()
+    );
+
+    let saved = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch delivery tasks.");
+
+    assert_eq!(saved.len(), 1);
+}
+
+#[tokio::test]
+async fn cancelled_newsletters_are_not_delivered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = post_scheduled_newsletter(&app).await;
+    let response = app.delete_newsletter_schedule(&issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.enqueue_due_issues().await;
+    app.dispatch_all_pending_emails().await;
+
+    let saved = sqlx::query!("SELECT scheduled_at, published_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+
+    assert!(saved.scheduled_at.is_none());
+    assert!(saved.published_at.is_none());
+}
+
+#[tokio::test]
+async fn newsletters_can_be_rescheduled() {
+    let app = spawn_app().await;
+    let issue_id = post_scheduled_newsletter(&app).await;
+    let scheduled_at = Utc::now() + Duration::days(3);
+
+    let response = app
+        .put_newsletter_schedule(
+            &issue_id,
+            serde_json::json!({ "scheduled_at": scheduled_at }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = sqlx::query!("SELECT scheduled_at FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved issue.");
+
+    assert_eq!(
+        saved.scheduled_at.unwrap().timestamp(),
+        scheduled_at.timestamp()
+    );
+}
+
+#[tokio::test]
+async fn scheduling_in_the_past_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let in_the_past = Utc::now() - Duration::hours(1);
+
+    let response = app
+        .post_newsletters(newsletter_request_body(Some(in_the_past)))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let issue_id = post_scheduled_newsletter(&app).await;
+    let response = app
+        .put_newsletter_schedule(
+            &issue_id,
+            serde_json::json!({ "scheduled_at": in_the_past }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn rescheduling_a_published_newsletter_is_rejected_with_a_409() {
+    let app = spawn_app().await;
+
+    let response = app.post_newsletters(newsletter_request_body(None)).await;
+    let body: serde_json::Value = response.json().await.unwrap();
+    let issue_id = body["newsletter_issue_id"].as_str().unwrap();
+
+    let scheduled_at = Utc::now() + Duration::hours(1);
+    let response = app
+        .put_newsletter_schedule(
+            issue_id,
+            serde_json::json!({ "scheduled_at": scheduled_at }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let response = app.delete_newsletter_schedule(issue_id).await;
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn scheduling_an_unknown_newsletter_returns_a_404() {
+    let app = spawn_app().await;
+    let scheduled_at = Utc::now() + Duration::hours(1);
+
+    let response = app
+        .put_newsletter_schedule(
+            &Uuid::new_v4().to_string(),
+            serde_json::json!({ "scheduled_at": scheduled_at }),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+fn newsletter_request_body(scheduled_at: Option<chrono::DateTime<Utc>>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "scheduled_at": scheduled_at,
+    })
+}
+
+// schedule an issue one hour from now and return its id
+async fn post_scheduled_newsletter(app: &TestApp) -> String {
+    let response = app
+        .post_newsletters(newsletter_request_body(Some(
+            Utc::now() + Duration::hours(1),
+        )))
         .await
         .error_for_status()
         .unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+
+    body["newsletter_issue_id"].as_str().unwrap().to_owned()
+}
+
+// the API refuses schedules in the past, so time is moved forward in the database instead
+async fn make_issue_due(app: &TestApp, issue_id: &str) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_at = now() - interval '1 minute' \
+        WHERE newsletter_issue_id = $1",
+        Uuid::parse_str(issue_id).unwrap()
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
 }
@@ -0,0 +1,67 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_newsletter::domain::SubscriberEmail;
+use zero2prod_newsletter::email_client::{EmailClient, EmailKind, SendEmailError};
+
+use crate::helpers::spawn_app_with;
+
+async fn send(email_client: &EmailClient) -> Result<(), SendEmailError> {
+    let recipient = SubscriberEmail::parse("ursula_le_guin@gmail.com".into()).unwrap();
+    email_client
+        .send_email(
+            EmailKind::Newsletter,
+            recipient,
+            "Subject",
+            "<p>Body</p>",
+            "Body",
+        )
+        .await
+}
+
+#[tokio::test]
+async fn the_daily_cap_is_shared_by_every_email_client() {
+    let mut configuration = None;
+    let app = spawn_app_with(|c| {
+        c.email_client.daily_cap = Some(2);
+        configuration = Some(c.clone());
+    })
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    // another instance, or the same one after a restart
+    let other_email_client = configuration
+        .unwrap()
+        .email_client
+        .client(app.db_pool.clone());
+
+    send(&app.email_client).await.unwrap();
+    send(&other_email_client).await.unwrap();
+    let outcome = send(&app.email_client).await;
+
+    assert!(matches!(outcome, Err(SendEmailError::DailyCapReached(_))));
+}
+
+#[tokio::test]
+async fn emails_the_provider_did_not_accept_do_not_use_up_the_daily_cap() {
+    let app = spawn_app_with(|c| c.email_client.daily_cap = Some(1)).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(422))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let rejected = send(&app.email_client).await;
+    let accepted = send(&app.email_client).await;
+
+    assert!(matches!(rejected, Err(SendEmailError::RequestFailed(_))));
+    assert!(accepted.is_ok());
+}
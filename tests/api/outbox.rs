@@ -0,0 +1,100 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app, TestApp};
+
+async fn subscribe(app: &TestApp) {
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn subscribe_does_not_wait_for_the_email_provider() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app).await;
+
+    let message = sqlx::query!("SELECT recipient, subject, status FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox message.");
+    assert_eq!(message.recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(message.subject, "Welcome!");
+    assert_eq!(message.status, "pending");
+}
+
+#[tokio::test]
+async fn dispatched_messages_are_marked_as_sent() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    app.dispatch_outbox().await;
+    // a sent message is not sent twice
+    app.dispatch_outbox().await;
+
+    let message = sqlx::query!("SELECT status, processed_at FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox message.");
+    assert_eq!(message.status, "sent");
+    assert!(message.processed_at.is_some());
+}
+
+#[tokio::test]
+async fn failed_messages_are_retried_later() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+
+    app.dispatch_outbox().await;
+
+    let message = sqlx::query!(
+        r#"SELECT status, n_attempts, next_attempt_at > now() AS "backed_off!" FROM email_outbox"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the outbox message.");
+    assert_eq!(message.status, "pending");
+    assert_eq!(message.n_attempts, 1);
+    assert!(message.backed_off);
+}
+
+#[tokio::test]
+async fn messages_are_given_up_on_after_too_many_attempts() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    subscribe(&app).await;
+    sqlx::query!("UPDATE email_outbox SET n_attempts = 4")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.dispatch_outbox().await;
+
+    let message = sqlx::query!("SELECT status, n_attempts FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the outbox message.");
+    assert_eq!(message.status, "failed");
+    assert_eq!(message.n_attempts, 5);
+}
@@ -0,0 +1,162 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_newsletter::configuration::{RateLimitBackend, TokenBucketSettings};
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+const ONE_REQUEST_PER_MINUTE: TokenBucketSettings = TokenBucketSettings {
+    capacity: 1,
+    refill_per_minute: 1,
+};
+
+async fn accept_emails(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn repeated_subscriptions_for_the_same_email_are_rejected_with_a_429() {
+    let app = spawn_app_with(|c| c.rate_limit.per_email = ONE_REQUEST_PER_MINUTE).await;
+    accept_emails(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let first = app.post_subscription(body.into()).await;
+    // the case of the address does not make it a different target
+    let second = app
+        .post_subscription("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    assert_eq!(second.headers()["Retry-After"], "60");
+}
+
+#[tokio::test]
+async fn subscriptions_for_different_emails_are_limited_separately() {
+    let app = spawn_app_with(|c| c.rate_limit.per_email = ONE_REQUEST_PER_MINUTE).await;
+    accept_emails(&app).await;
+
+    let first = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let second = app
+        .post_subscription("name=tolkien&email=jrr_tolkien%40gmail.com".into())
+        .await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn confirmations_are_limited_per_client_ip() {
+    let app = spawn_app_with(|c| c.rate_limit.per_ip = ONE_REQUEST_PER_MINUTE).await;
+    let confirm = format!("{}/subscriptions/confirm", app.address);
+
+    let first = reqwest::get(&confirm).await.unwrap();
+    let second = reqwest::get(&confirm).await.unwrap();
+
+    assert_eq!(first.status().as_u16(), 400);
+    assert_eq!(second.status().as_u16(), 429);
+    assert!(second.headers().contains_key("Retry-After"));
+}
+
+#[tokio::test]
+async fn buckets_that_never_refill_ask_to_retry_after_a_day() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.per_ip = TokenBucketSettings {
+            capacity: 1,
+            refill_per_minute: 0,
+        }
+    })
+    .await;
+    let confirm = format!("{}/subscriptions/confirm", app.address);
+
+    reqwest::get(&confirm).await.unwrap();
+    let second = reqwest::get(&confirm).await.unwrap();
+
+    assert_eq!(second.status().as_u16(), 429);
+    assert_eq!(second.headers()["Retry-After"], "86400");
+}
+
+#[tokio::test]
+async fn only_the_address_appended_by_the_proxy_is_limited() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.trust_proxy_headers = true;
+        c.rate_limit.per_ip = ONE_REQUEST_PER_MINUTE;
+    })
+    .await;
+    let confirm = format!("{}/subscriptions/confirm", app.address);
+    let client = reqwest::Client::new();
+
+    // the client rotates the entries it controls, the proxy always appends the same address
+    let first = client
+        .get(&confirm)
+        .header("X-Forwarded-For", "203.0.113.1, 198.51.100.2")
+        .send()
+        .await
+        .unwrap();
+    let second = client
+        .get(&confirm)
+        .header("X-Forwarded-For", "203.0.113.2, 198.51.100.2")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(first.status().as_u16(), 400);
+    assert_eq!(second.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn the_postgres_backend_shares_buckets_through_the_database() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.backend = RateLimitBackend::Postgres;
+        c.rate_limit.per_email = ONE_REQUEST_PER_MINUTE;
+    })
+    .await;
+    accept_emails(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let first = app.post_subscription(body.into()).await;
+    let second = app.post_subscription(body.into()).await;
+
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 429);
+    let bucket = sqlx::query!(
+        "SELECT tokens FROM rate_limit_buckets WHERE key = 'email:ursula_le_guin@gmail.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the rate limit bucket.");
+    assert!(bucket.tokens < 1.0);
+}
+
+#[tokio::test]
+async fn the_postgres_backend_deletes_buckets_that_refilled() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.backend = RateLimitBackend::Postgres;
+    })
+    .await;
+    accept_emails(&app).await;
+    sqlx::query!(
+        "INSERT INTO rate_limit_buckets (key, tokens, updated_at, full_at) \
+        VALUES ('ip:192.0.2.1', 0, now() - interval '1 hour', now() - interval '1 minute')"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let keys = sqlx::query!("SELECT key FROM rate_limit_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(keys.iter().all(|r| r.key != "ip:192.0.2.1"));
+    assert!(!keys.is_empty());
+}
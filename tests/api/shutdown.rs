@@ -0,0 +1,90 @@
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{spawn_app_with, TestApp};
+use zero2prod_newsletter::outbox_dispatcher::run_dispatcher_until_stopped;
+
+async fn get(app: &TestApp, path: &str) -> Result<reqwest::Response, reqwest::Error> {
+    // a new client for each request, so a pooled connection cannot outlive the listener
+    reqwest::Client::new()
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+}
+
+#[tokio::test]
+async fn health_ready_fails_as_soon_as_a_shutdown_starts() {
+    let app = spawn_app_with(|c| c.application.shutdown_drain_seconds = 5).await;
+
+    app.shutdown.cancel();
+    let response = get(&app, "/health/ready").await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "shutting_down");
+    // the server keeps serving while it drains
+    let response = get(&app, "/health_check").await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_server_stops_accepting_connections_after_draining() {
+    let app = spawn_app_with(|c| c.application.shutdown_drain_seconds = 0).await;
+    assert!(get(&app, "/health_check").await.is_ok());
+
+    app.shutdown.cancel();
+
+    let stopped = tokio::time::timeout(Duration::from_secs(5), async {
+        while get(&app, "/health_check").await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await;
+    assert!(
+        stopped.is_ok(),
+        "The server was still accepting connections."
+    );
+}
+
+#[tokio::test]
+async fn the_outbox_dispatcher_finishes_the_message_in_progress_before_stopping() {
+    let mut configuration = None;
+    let app = spawn_app_with(|c| configuration = Some(c.clone())).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let shutdown = CancellationToken::new();
+    let dispatcher = tokio::spawn(run_dispatcher_until_stopped(
+        configuration.unwrap(),
+        app.email_client.clone(),
+        shutdown.clone(),
+    ));
+
+    // the shutdown starts while the email is being sent
+    while app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    shutdown.cancel();
+    let outcome = tokio::time::timeout(Duration::from_secs(5), dispatcher).await;
+
+    assert!(outcome.expect("The dispatcher did not stop.").is_ok());
+    let status = sqlx::query_scalar!("SELECT status FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "sent");
+}
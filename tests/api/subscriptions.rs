@@ -1,7 +1,7 @@
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -31,6 +31,7 @@ async fn subscribe_persists_the_new_subscriber() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_outbox().await;
 
     let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
         .fetch_one(&app.db_pool)
@@ -87,6 +88,52 @@ async fn subscribe_returns_400_when_fields_are_present_but_invalid() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_returns_400_for_addresses_rejected_by_the_email_policy() {
+    let app = spawn_app_with(|c| c.email_policy.reject_role_accounts = true).await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "disposable email provider",
+        ),
+        ("name=Ursula&email=noreply%40example.com", "role account"),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_subscription(invalid_body.into()).await;
+
+        assert_eq!(400, response.status().as_u16());
+        assert!(
+            response.text().await.unwrap().contains(error_message),
+            "The API did not explain that the address is a {}",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_domain_of_the_email_in_lowercase_ascii() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=Ursula%40B%C3%BCcher.Example";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscription(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "Ursula@xn--bcher-kva.example");
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let app = spawn_app().await;
@@ -100,6 +147,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_outbox().await;
 }
 
 #[tokio::test]
@@ -114,8 +162,10 @@ async fn subscribe_sends_a_confirmation_email_with_a_link() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_outbox().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    #[allow(clippy::needless_borrow)]
     let confirmation_links = app.get_confirmation_links(&email_request);
 
     assert_eq!(confirmation_links.html, confirmation_links.plain_text)
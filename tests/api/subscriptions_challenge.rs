@@ -0,0 +1,156 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_newsletter::bot_protection::is_solved;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn get_challenge(app: &TestApp) -> serde_json::Value {
+    reqwest::get(format!("{}/subscriptions/challenge", app.address))
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+fn solve(challenge: &str, difficulty: u32) -> String {
+    (0u64..)
+        .map(|n| n.to_string())
+        .find(|solution| is_solved(challenge, solution, difficulty))
+        .unwrap()
+}
+
+async fn assert_discarded(app: &TestApp, response: reqwest::Response) {
+    // bots get no signal, the submission looks accepted
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn submissions_filling_the_honeypot_are_discarded() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=https%3A%2F%2Fspam.com".into(),
+        )
+        .await;
+    app.dispatch_outbox().await;
+
+    assert_discarded(&app, response).await;
+}
+
+#[tokio::test]
+async fn submissions_without_a_challenge_are_discarded_when_one_is_required() {
+    let app = spawn_app_with(|c| c.bot_protection.require_challenge = true).await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    assert_discarded(&app, response).await;
+}
+
+#[tokio::test]
+async fn submissions_sent_right_after_the_challenge_are_discarded() {
+    let app = spawn_app_with(|c| c.bot_protection.require_challenge = true).await;
+    let challenge = get_challenge(&app).await;
+    let challenge_token = challenge["challenge"].as_str().unwrap();
+    let difficulty = challenge["difficulty"].as_u64().unwrap() as u32;
+
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("challenge", challenge_token),
+        ("solution", &solve(challenge_token, difficulty)),
+    ])
+    .unwrap();
+    let response = app.post_subscription(body).await;
+
+    assert_discarded(&app, response).await;
+}
+
+#[tokio::test]
+async fn submissions_with_a_solved_challenge_are_accepted() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.require_challenge = true;
+        c.bot_protection.min_solve_seconds = 0;
+        c.bot_protection.proof_of_work_difficulty = 8;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let challenge = get_challenge(&app).await;
+    let challenge_token = challenge["challenge"].as_str().unwrap();
+    assert_eq!(challenge["difficulty"], 8);
+
+    let body = serde_urlencoded::to_string([
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("challenge", challenge_token),
+        ("solution", &solve(challenge_token, 8)),
+    ])
+    .unwrap();
+    let response = app.post_subscription(body).await;
+    app.dispatch_outbox().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn a_solved_challenge_cannot_be_used_twice() {
+    let app = spawn_app_with(|c| {
+        c.bot_protection.require_challenge = true;
+        c.bot_protection.min_solve_seconds = 0;
+        c.bot_protection.proof_of_work_difficulty = 8;
+    })
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let challenge = get_challenge(&app).await;
+    let challenge_token = challenge["challenge"].as_str().unwrap();
+    let solution = solve(challenge_token, 8);
+
+    for email in ["ursula_le_guin@gmail.com", "octavia_butler@gmail.com"] {
+        let body = serde_urlencoded::to_string([
+            ("name", "le guin"),
+            ("email", email),
+            ("challenge", challenge_token),
+            ("solution", &solution),
+        ])
+        .unwrap();
+        let response = app.post_subscription(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    app.dispatch_outbox().await;
+
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+}
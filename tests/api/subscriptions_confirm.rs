@@ -26,8 +26,10 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_outbox().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    #[allow(clippy::needless_borrow)]
     let confirmation_links = app.get_confirmation_links(&email_request);
 
     let response = reqwest::get(confirmation_links.html).await.unwrap();
@@ -47,8 +49,10 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
 
     app.post_subscription(body.into()).await;
+    app.dispatch_outbox().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    #[allow(clippy::needless_borrow)]
     let confirmation_links = app.get_confirmation_links(&email_request);
 
     reqwest::get(confirmation_links.html)
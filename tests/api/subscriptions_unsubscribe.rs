@@ -0,0 +1,175 @@
+use crate::helpers::{
+    create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app, TestApp,
+};
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+async fn post_unsubscribe(app: &TestApp, unsubscribe_token: &str, body: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+            app.address, unsubscribe_token
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn saved_unsubscribe_token(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+}
+
+async fn saved_status(app: &TestApp) -> String {
+    sqlx::query_scalar!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+}
+
+#[tokio::test]
+async fn unsubscribing_without_token_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!("{}/subscriptions/unsubscribe", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn unsubscribing_with_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_no_longer_receive_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let saved = sqlx::query!("SELECT unsubscribe_token FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    post_unsubscribe(&app, &saved.unsubscribe_token, "")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status, unsubscribed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+    assert!(saved.unsubscribed_at.is_some());
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn confirmation_links_do_not_resubscribe_an_unsubscribed_subscriber() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_subscriber(&app).await;
+
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_only_shows_a_confirmation_page() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_token = saved_unsubscribe_token(&app).await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/unsubscribe?unsubscribe_token={}",
+        app.address, unsubscribe_token
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"method="post""#));
+    assert!(page.contains(&unsubscribe_token));
+    assert_eq!(saved_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn mail_clients_can_unsubscribe_with_one_click() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let unsubscribe_token = saved_unsubscribe_token(&app).await;
+
+    let response = post_unsubscribe(&app, &unsubscribe_token, "List-Unsubscribe=One-Click").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn posting_an_unknown_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = post_unsubscribe(&app, "unknown", "List-Unsubscribe=One-Click").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn unsubscribing_keeps_the_status_of_a_suppressed_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'suppressed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let unsubscribe_token = saved_unsubscribe_token(&app).await;
+
+    let response = post_unsubscribe(&app, &unsubscribe_token, "List-Unsubscribe=One-Click").await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_status(&app).await, "suppressed");
+}
@@ -0,0 +1,175 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use uuid::Uuid;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn suppressions_can_be_added_listed_and_removed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_suppression(serde_json::json!({
+            "kind": "domain",
+            "value": "Example.com",
+            "reason": "Requested by the domain owner",
+            "source": "legal_request",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let entry_id = body["entry_id"].as_str().unwrap();
+
+    let entries: serde_json::Value = app.get_suppressions().await.json().await.unwrap();
+    assert_eq!(entries.as_array().unwrap().len(), 1);
+    assert_eq!(entries[0]["kind"], "domain");
+    assert_eq!(entries[0]["value"], "example.com");
+    assert_eq!(entries[0]["source"], "legal_request");
+
+    let response = app.delete_suppression(entry_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let entries: serde_json::Value = app.get_suppressions().await.json().await.unwrap();
+    assert!(entries.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn duplicate_suppressions_are_rejected_with_a_409() {
+    let app = spawn_app().await;
+    let body = serde_json::json!({
+        "kind": "address",
+        "value": "ursula_le_guin@gmail.com",
+        "reason": "Asked by phone",
+        "source": "manual",
+    });
+
+    assert_eq!(
+        app.post_suppression(body.clone()).await.status().as_u16(),
+        201
+    );
+    assert_eq!(app.post_suppression(body).await.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"kind": "address", "value": "not-an-email", "reason": "-", "source": "manual"}),
+            "invalid address",
+        ),
+        (
+            serde_json::json!({"kind": "domain", "value": "localhost", "reason": "-", "source": "manual"}),
+            "invalid domain",
+        ),
+        (
+            serde_json::json!({"kind": "domain", "value": "example.com", "reason": "-", "source": "gossip"}),
+            "unknown source",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_suppression(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn removing_an_unknown_suppression_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app.delete_suppression(&Uuid::new_v4().to_string()).await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_not_sent_to_suppressed_domains() {
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({
+        "kind": "domain",
+        "value": "gmail.com",
+        "reason": "Test",
+        "source": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    app.dispatch_outbox().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_suppressed_domain_covers_its_subdomains() {
+    let app = spawn_app().await;
+    app.post_suppression(serde_json::json!({
+        "kind": "domain",
+        "value": "example.com",
+        "reason": "Test",
+        "source": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscription("name=le%20guin&email=ursula%40mail.example.com".into())
+        .await;
+    app.dispatch_outbox().await;
+
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_addresses() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_suppression(serde_json::json!({
+        "kind": "address",
+        "value": "Ursula_Le_Guin@gmail.com",
+        "reason": "Test",
+        "source": "manual",
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
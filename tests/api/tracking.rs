@@ -0,0 +1,144 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_for_tracked_issues() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let body = send_issue(&app, true).await;
+    let html = body["HtmlBody"].as_str().unwrap();
+
+    assert!(!html.contains(r#"href="https://example.com/post""#));
+    assert!(html.contains(&format!(
+        "href=\"{}/subscriptions/unsubscribe",
+        app.base_url
+    )));
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client
+        .get(tracking_link(&app, html, "/t/c/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(
+        response.headers()["Location"].to_str().unwrap(),
+        "https://example.com/post"
+    );
+
+    let response = client
+        .get(tracking_link(&app, html, "/t/o/"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+
+    let events = sqlx::query!(
+        r#"
+        SELECT e.kind, e.url, d.subscriber_email
+        FROM tracking_events e
+        JOIN issue_deliveries d ON d.delivery_id = e.delivery_id
+        ORDER BY e.kind
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].kind, "click");
+    assert_eq!(events[0].url.as_deref(), Some("https://example.com/post"));
+    assert_eq!(events[1].kind, "open");
+    assert_eq!(events[1].subscriber_email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn untracked_issues_are_sent_as_they_are() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let body = send_issue(&app, false).await;
+    let html = body["HtmlBody"].as_str().unwrap();
+
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn nothing_is_tracked_when_tracking_is_disabled_globally() {
+    let mut app = spawn_app().await;
+    app.tracking.enabled = false;
+    create_confirmed_subscriber(&app).await;
+
+    let body = send_issue(&app, true).await;
+    let html = body["HtmlBody"].as_str().unwrap();
+
+    assert!(html.contains(r#"href="https://example.com/post""#));
+    assert!(!html.contains("/t/o/"));
+}
+
+#[tokio::test]
+async fn forged_click_links_are_rejected_with_a_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}/t/c/aGVsbG8.d29ybGQ", app.address))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+// publish an issue with a single external link and return the email sent for it
+async fn send_issue(app: &TestApp, tracked: bool) -> serde_json::Value {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p><a href=\"https://example.com/post\">Read more</a></p>\
+                <p><a href=\"{{unsubscribe_url}}\">Unsubscribe</a></p>",
+        },
+        "track_opens": tracked,
+        "track_clicks": tracked,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+// the base url of the test configuration has no port, the one of the test app is added
+fn tracking_link(app: &TestApp, html: &str, prefix: &str) -> reqwest::Url {
+    let link = linkify::LinkFinder::new()
+        .links(html)
+        .map(|l| l.as_str().to_owned())
+        .find(|l| l.contains(prefix))
+        .unwrap();
+    let mut link = reqwest::Url::parse(&link).unwrap();
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
@@ -0,0 +1,192 @@
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+use secrecy::ExposeSecret;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn webhook_requests_missing_authorization_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .json(&bounce_event(1, "HardBounce"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn admin_credentials_do_not_authenticate_webhooks() {
+    let app = spawn_app().await;
+
+    let response = app
+        .authenticated(reqwest::Method::POST, "/webhooks/postmark")
+        .json(&bounce_event(1, "HardBounce"))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn webhook_credentials_do_not_authenticate_the_admin_api() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/issues", &app.address))
+        .basic_auth(
+            &app.webhooks.username,
+            Some(app.webhooks.password.expose_secret()),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn hard_bounces_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_postmark_webhook(bounce_event(1, "HardBounce"))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+    let event = sqlx::query!("SELECT record_type, event_type, email FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved event.");
+    assert_eq!(event.record_type, "Bounce");
+    assert_eq!(event.event_type, "HardBounce");
+    assert_eq!(event.email, "ursula_le_guin@gmail.com");
+    let entries: serde_json::Value = app.get_suppressions().await.json().await.unwrap();
+    assert_eq!(entries[0]["value"], "ursula_le_guin@gmail.com");
+    assert_eq!(entries[0]["source"], "bounce");
+}
+
+#[tokio::test]
+async fn spam_complaints_suppress_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_postmark_webhook(serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "ID": 2,
+            "Type": "SpamComplaint",
+            "TypeCode": 512,
+            "MessageID": "00000000-0000-0000-0000-000000000000",
+            "Email": "Ursula_Le_Guin@gmail.com",
+            "BouncedAt": "2026-10-19T16:33:54.9070259Z",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "suppressed");
+}
+
+#[tokio::test]
+async fn soft_bounces_are_recorded_without_suppressing_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let response = app
+        .post_postmark_webhook(bounce_event(3, "SoftBounce"))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let events = sqlx::query!("SELECT event_type FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn retried_events_are_recorded_once() {
+    let app = spawn_app().await;
+
+    for _ in 0..2 {
+        let response = app
+            .post_postmark_webhook(bounce_event(4, "HardBounce"))
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let events = sqlx::query!("SELECT event_id FROM email_events")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+#[tokio::test]
+async fn unsupported_record_types_are_rejected_with_a_422() {
+    let app = spawn_app().await;
+
+    let mut event = bounce_event(5, "HardBounce");
+    event["RecordType"] = "Delivery".into();
+    let response = app.post_postmark_webhook(event).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // the issue is enqueued before the bounce comes in, the pending task must be skipped too
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.post_postmark_webhook(bounce_event(6, "HardBounce"))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+fn bounce_event(id: i64, event_type: &str) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": id,
+        "Type": event_type,
+        "TypeCode": 1,
+        "MessageID": "00000000-0000-0000-0000-000000000000",
+        "Description": "The server was unable to deliver your message.",
+        "Details": "smtp;550 5.1.1 The email account that you tried to reach does not exist.",
+        "Email": "ursula_le_guin@gmail.com",
+        "BouncedAt": "2026-10-19T16:33:54.9070259Z",
+        "Inactive": true,
+    })
+}
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .status
+}